@@ -11,13 +11,15 @@ pub struct BootInfo<'a> {
     pub rtc_boot: Option<Duration>,
     pub paging_level: Option<Mode>,
     pub memory_map: Option<&'a MemoryMapResponse>,
+    // Physical address of the ACPI RSDP.
+    pub rsdp: Option<u64>,
 }
 
 #[derive(Default)]
 pub struct KernelContext<'a> {
     pub framebuffer: Option<Framebuffer<'a>>,
     // I hate myself for this.
-    pub vga: Option<VgaSink<'a>>,
+    pub vga: Option<VgaSink>,
     pub logger: Option<Logger<'a>>,
     pub boot_info: BootInfo<'a>,
 }
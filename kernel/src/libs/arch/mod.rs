@@ -11,8 +11,36 @@ cfg_select! {
 
 pub mod paging;
 
+use crate::libs::generic::memory::address::VirtAddr;
+
 pub fn init() {
     unsafe {
         internal::init();
     }
 }
+
+// Second stage, once the kernel address space is set up.
+pub fn late_init() {
+    internal::late_init();
+}
+
+// Hardware identifier of the running CPU.
+#[inline]
+pub fn current_cpu_id() -> u32 {
+    internal::apic_id()
+}
+
+// NUMA node of the running CPU, worked out once the topology is known.
+#[inline]
+pub fn current_numa_node() -> usize {
+    internal::numa_node()
+}
+
+pub fn set_current_numa_node(node: usize) {
+    internal::set_numa_node(node);
+}
+
+// Continue execution in `entry` on the stack ending at `top`, never returns to the caller.
+pub unsafe fn switch_stack(top: VirtAddr, entry: extern "C" fn() -> !) -> ! {
+    unsafe { internal::asm::switch_stack(top.into(), entry) }
+}
@@ -1,21 +1,49 @@
 use super::internal;
-use crate::libs::generic::memory::{address::PhysAddr, paging::PaginationLevel};
+use crate::libs::generic::memory::{address::{PhysAddr, VirtAddr}, paging::PaginationLevel};
 
 #[inline]
 pub fn get_max_level() -> PaginationLevel {
     internal::memory::paging::get_max_level()
 }
 
+// Highest level at which an entry can directly map a page.
+#[inline]
+pub fn get_max_leaf_level() -> PaginationLevel {
+    internal::memory::paging::get_max_leaf_level()
+}
+
 #[inline]
 pub fn get_page_frame_size() -> usize {
     internal::memory::paging::get_page_frame_size()
 }
 
+#[inline]
+pub fn get_virtual_address_bits() -> u32 {
+    internal::memory::paging::get_virtual_address_bits()
+}
+
+#[inline]
+pub fn get_kernel_space_start() -> VirtAddr {
+    VirtAddr::try_from(internal::memory::paging::get_kernel_space_start()).unwrap()
+}
+
 #[inline]
 pub fn enforce_canonical() -> bool {
     internal::memory::paging::enforce_canonical()
 }
 
+// Set up the caching types selected by MemoryType::flags.
+#[inline]
+pub fn init_memory_types() {
+    internal::memory::paging::init_memory_types();
+}
+
+// Enable non-executable pages, before loading a page table that uses them.
+#[inline]
+pub fn init_no_execute() {
+    internal::memory::paging::init_no_execute();
+}
+
 #[inline]
 pub fn set_page_table_addr(addr: PhysAddr) {
     internal::memory::paging::set_page_table_addr(addr);
@@ -26,6 +54,33 @@ pub fn get_page_table_addr() -> PhysAddr {
     internal::memory::paging::get_page_table_addr()
 }
 
+// TLB flushes only apply to the current CPU, see generic::memory::paging::tlb for shootdowns.
+#[inline]
+pub fn flush_tlb_page(addr: VirtAddr) {
+    internal::memory::paging::flush_tlb_page(addr);
+}
+
+#[inline]
+pub fn flush_tlb_range(addr: VirtAddr, length: usize) {
+    internal::memory::paging::flush_tlb_range(addr, length);
+}
+
+// Every translation but the global ones.
+#[inline]
+pub fn flush_tlb() {
+    internal::memory::paging::flush_tlb();
+}
+
+#[inline]
+pub fn flush_tlb_global() {
+    internal::memory::paging::flush_tlb_global();
+}
+
+#[inline]
+pub fn send_tlb_shootdown() {
+    internal::memory::paging::send_tlb_shootdown();
+}
+
 #[inline]
 pub fn get_page_level_size() -> usize {
     internal::memory::paging::get_page_level_size()
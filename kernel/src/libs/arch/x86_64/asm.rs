@@ -31,6 +31,13 @@ pub unsafe fn inb(port: usize) -> u8 {
     }
 }
 
+#[inline]
+pub unsafe fn invlpg(addr: u64) {
+    unsafe {
+        asm!("invlpg [{addr}]", addr = in(reg) addr, options(nostack));
+    }
+}
+
 #[inline]
 pub unsafe fn load_gdt(gdtr: &GdtDescriptor) {
     debug!("Loading GDT at address {:02x}", gdtr.gdt as u64);
@@ -60,6 +67,45 @@ pub unsafe fn load_gdt(gdtr: &GdtDescriptor) {
     }
 }
 
+#[inline]
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+
+    unsafe {
+        asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nostack));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+#[inline]
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    unsafe {
+        asm!("wrmsr", in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32, options(nostack));
+    }
+}
+
+// Jump to `entry` on a new stack, the current one is abandoned.
+pub unsafe fn switch_stack(top: u64, entry: extern "C" fn() -> !) -> ! {
+    unsafe {
+        asm!(
+            "mov rsp, {top}",
+            "xor rbp, rbp",
+            "call {entry}",
+            top = in(reg) top,
+            entry = in(reg) entry,
+            options(noreturn)
+        );
+    }
+}
+
+#[inline]
+pub unsafe fn load_tss(selector: u16) {
+    unsafe {
+        asm!("ltr {0:x}", in(reg) selector, options(nostack));
+    }
+}
+
 pub enum CpuIdRegisterOrder {
     EAX = 0,
     EBX = 1,
@@ -74,7 +120,6 @@ pub unsafe fn cpuid(request: CpuIdRequest) -> [u32; 4] {
     unsafe {
         asm!(
             "mov {0:r}, rbx",
-            "mov rax, 1",
             "mov rcx, 0", // TODO: Add support for subleaves
             "cpuid",
             "xchg {0:r}, rbx",
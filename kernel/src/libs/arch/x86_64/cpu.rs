@@ -2,10 +2,12 @@ use crate::libs::arch::x86_64::asm::{CpuIdRegisterOrder, cpuid};
 use crate::warning;
 use bitflags::bitflags;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
+#[repr(u32)]
 pub enum CpuIdRequest {
     BasicFeatures = 0x01,
     ExtendedFeatures = 0x07,
+    ExtendedProcessorFeatures = 0x8000_0001,
 }
 
 bitflags! {
@@ -78,14 +80,47 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Default)]
+    pub struct ExtendedProcessorFeaturesFlags: u64 {
+        /* EDX */
+        const SYSCALL = 1 << 11;
+        const NX = 1 << 20;
+        const MMXEXT = 1 << 22;
+        const FXSR_OPT = 1 << 25;
+        const PAGE1GB = 1 << 26;
+        const RDTSCP = 1 << 27;
+        const LM = 1 << 29;
+
+        /* ECX */
+        const LAHF_LM = 1 << 32;
+        const SVM = 1 << 34;
+        const ABM = 1 << 37;
+        const SSE4A = 1 << 38;
+    }
+}
+
+// Initial APIC ID of the running CPU.
+pub fn apic_id() -> u32 {
+    let request_result: [u32; 4] = unsafe { cpuid(CpuIdRequest::BasicFeatures) };
+
+    request_result[CpuIdRegisterOrder::EBX as usize] >> 24
+}
+
 #[derive(Default)]
 pub struct BasicFeatures {
     pub flags: BasicFeaturesFlags,
 }
 
+#[derive(Default)]
+pub struct ExtendedProcessorFeatures {
+    pub flags: ExtendedProcessorFeaturesFlags,
+}
+
 #[derive(Default)]
 pub struct CpuInfo {
     pub basic_features: Option<BasicFeatures>,
+    pub extended_processor_features: Option<ExtendedProcessorFeatures>,
 }
 
 impl CpuInfo {
@@ -97,7 +132,7 @@ impl CpuInfo {
 
     pub unsafe fn request(&mut self, request: CpuIdRequest) {
         // TODO: Check if CPUID is supported
-        let request_result: [u32; 4] = unsafe { cpuid(CpuIdRequest::BasicFeatures.into()) };
+        let request_result: [u32; 4] = unsafe { cpuid(request) };
 
         match request {
             CpuIdRequest::BasicFeatures => {
@@ -108,6 +143,14 @@ impl CpuInfo {
                     ),
                 });
             }
+            CpuIdRequest::ExtendedProcessorFeatures => {
+                self.extended_processor_features = Some(ExtendedProcessorFeatures {
+                    flags: ExtendedProcessorFeaturesFlags::from_bits_truncate(
+                        request_result[CpuIdRegisterOrder::EDX as usize] as u64
+                            | ((request_result[CpuIdRegisterOrder::ECX as usize] as u64) << 32),
+                    ),
+                });
+            }
             _ => {
                 warning!("Unsupported CPU ID request: {:?}", request);
             }
@@ -1,9 +1,12 @@
-use crate::libs::arch::x86_64::asm::load_gdt;
+use crate::libs::arch::x86_64::asm::{load_gdt, load_tss};
 use bitflags::bitflags;
 
 pub const CPL_RING_3: u8 = 0b11; // Usermode CPU privilege level
 pub const CPL_RING_0: u8 = 0b00; // Kernel CPU privilege level
 
+pub const GDT_ENTRIES: usize = 7;
+pub const TSS_SELECTOR: u16 = 5 << 3;
+
 bitflags! {
     struct GdtAccessByte: u8 {
         const Accessed = 1;
@@ -36,6 +39,41 @@ struct GdtSegmentDescriptor {
     pub access: GdtAccessByte,
 }
 
+// 64-bit Task State Segment, only used for the stacks the CPU switches to on interrupts.
+#[repr(C, packed)]
+pub struct Tss {
+    _reserved0: u32,
+    // Stacks loaded when entering ring 0 to 2 from a lower privilege.
+    pub rsp: [u64; 3],
+    _reserved1: u64,
+    // Interrupt stack table, IDT entries refer to it with 1-based indices.
+    pub ist: [u64; 7],
+    _reserved2: u64,
+    _reserved3: u16,
+    pub iomap_base: u16,
+}
+
+impl Default for Tss {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tss {
+    pub const fn new() -> Self {
+        Self {
+            _reserved0: 0,
+            rsp: [0; 3],
+            _reserved1: 0,
+            ist: [0; 7],
+            _reserved2: 0,
+            _reserved3: 0,
+            // No I/O permission bitmap.
+            iomap_base: size_of::<Tss>() as u16,
+        }
+    }
+}
+
 #[repr(C, packed)]
 pub struct GdtDescriptor {
     pub size: u16,
@@ -70,7 +108,27 @@ impl Into<u64> for GdtSegmentDescriptor {
     }
 }
 
-pub fn load(gdt: &'static mut [u64; 5]) {
+// A TSS descriptor is a system segment taking two GDT entries, the second one holds
+// the upper half of the base address.
+fn tss_descriptor(tss: &'static Tss) -> [u64; 2] {
+    let base = tss as *const Tss as u64;
+
+    [
+        GdtSegmentDescriptor {
+            base: base as u32,
+            limit: (size_of::<Tss>() - 1) as u32,
+            // Type 0x9: available 64-bit TSS.
+            access: GdtAccessByte::Present | GdtAccessByte::Executable | GdtAccessByte::Accessed,
+            flags: GdtFlag::empty(),
+        }
+        .into(),
+        base >> 32,
+    ]
+}
+
+pub fn load(gdt: &'static mut [u64; GDT_ENTRIES], tss: &'static Tss) {
+    let [tss_low, tss_high] = tss_descriptor(tss);
+
     *gdt = [
         // Null descriptor
         GdtSegmentDescriptor {
@@ -128,7 +186,8 @@ pub fn load(gdt: &'static mut [u64; 5]) {
             flags: GdtFlag::Granularity | GdtFlag::Size,
         }
         .into(),
-        // TODO: Add TSS
+        tss_low,
+        tss_high,
     ];
     let gdtr = GdtDescriptor {
         gdt: gdt.as_ptr(),
@@ -137,6 +196,7 @@ pub fn load(gdt: &'static mut [u64; 5]) {
 
     unsafe {
         load_gdt(&gdtr);
+        load_tss(TSS_SELECTOR);
     }
 }
 
@@ -146,6 +206,7 @@ mod tests {
     use crate::libs::arch::x86_64::gdt::GdtDescriptor;
     use crate::libs::arch::x86_64::gdt::GdtFlag;
     use crate::libs::arch::x86_64::gdt::GdtSegmentDescriptor;
+    use crate::libs::arch::x86_64::gdt::Tss;
 
     #[test]
     fn gdt_test_serialize_gdtsegdesc() {
@@ -170,5 +231,6 @@ mod tests {
     #[test]
     fn gdt_test_struct_sizes() {
         assert_eq!(size_of::<GdtDescriptor>() * 8, 80);
+        assert_eq!(size_of::<Tss>(), 104);
     }
 }
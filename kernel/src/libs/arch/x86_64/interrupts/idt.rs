@@ -46,6 +46,18 @@ impl Into<u8> for IdtGateDescriptorProperties {
 }
 
 impl IdtGateDescriptor {
+    pub const fn empty() -> Self {
+        Self {
+            ep_ll: 0,
+            segment_selector: 0,
+            ist_offset: 0,
+            properties: 0,
+            ep_lh: 0,
+            ep_hh: 0,
+            _reserved: 0,
+        }
+    }
+
     pub fn new(
         entry_point: u64,
         segment_selector: SegmentSelector,
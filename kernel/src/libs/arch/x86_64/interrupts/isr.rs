@@ -1,7 +1,44 @@
-use crate::libs::arch::x86_64::{interrupts::ctx::Context, registers};
+use crate::libs::{
+    arch::x86_64::{interrupts::ctx::Context, lapic, registers},
+    generic::memory::{address::VirtAddr, paging::tlb, stack, vm::{self, PageFault, VmError}},
+};
+use bitflags::bitflags;
 use core::arch::naked_asm;
 use seq_macro::seq;
 
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct PageFaultErrorCode: u64 {
+        const Present = 1;
+        const Write = 1 << 1;
+        const User = 1 << 2;
+        const ReservedBit = 1 << 3;
+        const InstructionFetch = 1 << 4;
+        const ProtectionKey = 1 << 5;
+        const ShadowStack = 1 << 6;
+    }
+}
+
+// Returns Ok if the fault was resolved and the instruction can be retried.
+fn handle_page_fault(context: &Context) -> Result<(), VmError> {
+    let error_code = PageFaultErrorCode::from_bits_truncate(context.error_code);
+    let Ok(address) = VirtAddr::try_from(registers::cr2()) else {
+        return Err(VmError::NotFound);
+    };
+
+    // A reserved bit set in a paging structure is a kernel bug, never retry.
+    if error_code.contains(PageFaultErrorCode::ReservedBit) {
+        return Err(VmError::AccessViolation);
+    }
+    vm::handle_page_fault(&PageFault {
+        address,
+        present: error_code.contains(PageFaultErrorCode::Present),
+        write: error_code.contains(PageFaultErrorCode::Write),
+        user: error_code.contains(PageFaultErrorCode::User),
+        execute: error_code.contains(PageFaultErrorCode::InstructionFetch),
+    })
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn generic_handler(_context: *mut Context) {
     let context = unsafe { *_context };
@@ -9,15 +46,46 @@ pub extern "C" fn generic_handler(_context: *mut Context) {
     if context.isr_index == 0x1 {
         return;
     }
+    if context.isr_index == lapic::TLB_SHOOTDOWN_VECTOR as u64 {
+        tlb::handle_shootdown();
+        lapic::eoi();
+        return;
+    }
     match context.isr_index {
         0xE => {
+            match handle_page_fault(&context) {
+                Ok(()) => return,
+                Err(VmError::StackOverflow(owner)) => panic!(
+                    "Kernel stack overflow ({:?}) while accessing address 0x{:02x}\n\n{:?}{:?}",
+                    owner,
+                    registers::cr2(),
+                    context,
+                    context.registers
+                ),
+                Err(VmError::WouldDeadlock) => panic!(
+                    "Page fault at address 0x{:02x} while the memory manager was busy, it can't be resolved\n\n{:?}{:?}",
+                    registers::cr2(),
+                    context,
+                    context.registers
+                ),
+                Err(_) => {}
+            }
             panic!(
-                "Unhandled page fault occured while reading address 0x{:02x}\n\n{:?}{:?}",
+                "Unhandled page fault occured while accessing address 0x{:02x} ({:?})\n\n{:?}{:?}",
                 registers::cr2(),
+                PageFaultErrorCode::from_bits_truncate(context.error_code),
                 context,
                 context.registers
             );
         }
+        // A kernel stack overflow faults again while pushing the page fault frame, CR2 is in the guard page.
+        0x8 if let Some(owner) = VirtAddr::try_from(registers::cr2()).ok().and_then(stack::guard_owner) => panic!(
+            "Kernel stack overflow ({:?}) while accessing address 0x{:02x} (double fault)\n\n{:?}{:?}",
+            owner,
+            registers::cr2(),
+            context,
+            context.registers
+        ),
         _ => panic!(
             "An unhandled CPU interrupt occured, {} (error code: {:x}, raw: {})\n\n{:?}{:?}",
             match context.isr_index {
@@ -28,6 +96,7 @@ pub extern "C" fn generic_handler(_context: *mut Context) {
                 0x5 => "bound range exceeded",
                 0x6 => "invalid opcode",
                 0x7 => "device not available (no math coprocessor)",
+                0x8 => "double fault",
                 0x9 => "coprocessor segment overrun",
                 0x10 => "x87 floating point exception",
                 0x12 => "machine check",
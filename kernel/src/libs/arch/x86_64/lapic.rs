@@ -0,0 +1,56 @@
+use core::hint::spin_loop;
+
+use crate::libs::{
+    arch::x86_64::{asm::rdmsr, memory::paging::MemoryType},
+    generic::memory::{
+        address::PhysAddr,
+        mmio::{ioremap, IoMapping},
+    },
+};
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_MASK: u64 = 0xFFFFFFFFFF000;
+
+// Size of the register page.
+const LAPIC_SIZE: usize = 0x400;
+
+const REG_EOI: usize = 0xB0;
+const REG_ICR_LOW: usize = 0x300;
+const REG_ICR_HIGH: usize = 0x310;
+
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+pub const TLB_SHOOTDOWN_VECTOR: u8 = 0xFD;
+
+// Local APIC registers, mapped uncached in the kernel address space.
+static mut LAPIC: Option<IoMapping> = None;
+
+// Only maps the registers, the APIC itself is left as the bootloader configured it.
+pub fn init() {
+    let phys_addr = PhysAddr::from(unsafe { rdmsr(IA32_APIC_BASE) } & APIC_BASE_MASK);
+    let registers = ioremap(phys_addr, LAPIC_SIZE, MemoryType::Uncached).expect("Failed to map the local APIC.");
+
+    unsafe { LAPIC = Some(registers) };
+}
+
+fn read(register: usize) -> u32 {
+    unsafe { LAPIC.as_ref().expect("Local APIC used before being mapped.").read::<u32>(register) }
+}
+
+fn write(register: usize, value: u32) {
+    unsafe { LAPIC.as_ref().expect("Local APIC used before being mapped.").write::<u32>(register, value) };
+}
+
+pub fn eoi() {
+    write(REG_EOI, 0);
+}
+
+pub fn send_ipi_all_but_self(vector: u8) {
+    write(REG_ICR_HIGH, 0);
+    write(REG_ICR_LOW, vector as u32 | ICR_ASSERT | ICR_ALL_EXCLUDING_SELF);
+    while read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+        spin_loop();
+    }
+}
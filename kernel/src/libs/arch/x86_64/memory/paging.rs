@@ -1,10 +1,10 @@
 use bitflags::bitflags;
 use limine::paging::Mode;
 
-use crate::{libs::{arch::x86_64::registers::{cr3, write_cr3}, generic::memory::{address::PhysAddr, paging::PaginationLevel}}, KERNEL_CONTEXT};
+use crate::{libs::{arch::x86_64::{asm::{invlpg, rdmsr, wrmsr}, cpu::{BasicFeaturesFlags, ExtendedProcessorFeaturesFlags}, lapic, registers::{cr3, cr4, write_cr3, write_cr4}, CPU_CONTEXT}, generic::memory::{address::{PhysAddr, VirtAddr}, paging::PaginationLevel}}, warning, KERNEL_CONTEXT};
 
 bitflags!(
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, PartialEq, Eq)]
     pub struct PageEntryFlags: u64 {
         const Present = 1;
         const ReadWrite = 1 << 1;
@@ -14,13 +14,108 @@ bitflags!(
         const Accessed = 1 << 5;
         const Dirty = 1 << 6;
         const PageAttributeTable = 1 << 7;
+        // Same bit as PAT, but in L2/L3 entries it makes the entry map a 2MiB/1GiB page.
+        const HugePage = 1 << 7;
         const Global = 1 << 8;
+        // Bits 9 to 11 are ignored by the MMU and free for the kernel to use.
+        const CopyOnWrite = 1 << 9;
         const ExecuteDisabled = 1 << 63;
     }
 );
 
 pub const ADDRESS_MASK: u64 = 0xFFFFFFFFFF000;
 
+const CR4_PGE: u64 = 1 << 7;
+
+const IA32_PAT: u32 = 0x277;
+const IA32_EFER: u32 = 0xC000_0080;
+const EFER_NXE: u64 = 1 << 11;
+
+// Memory type encodings of the PAT entries.
+const PAT_UC: u64 = 0x00;
+const PAT_WC: u64 = 0x01;
+const PAT_WT: u64 = 0x04;
+const PAT_WP: u64 = 0x05;
+const PAT_WB: u64 = 0x06;
+const PAT_UC_MINUS: u64 = 0x07;
+
+// Entry selected by the PAT, PCD and PWT bits of a page, in that order.
+// The first four only differ from the power-on layout by WC replacing WT, so that
+// write-combining doesn't need the PAT bit (which moves to bit 12 in huge pages).
+const PAT_LAYOUT: [u64; 8] = [PAT_WB, PAT_WC, PAT_UC_MINUS, PAT_UC, PAT_WB, PAT_WP, PAT_UC_MINUS, PAT_WT];
+
+// Caching behaviour of a mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+    WriteBack,
+    // Writes are buffered and merged, for framebuffers.
+    WriteCombining,
+    // Strong uncacheable, for MMIO.
+    Uncached,
+    // Uncacheable, unless the MTRRs say write-combining.
+    UncachedMinus,
+    WriteThrough,
+}
+
+impl MemoryType {
+    // Page flags selecting this type. Types using the PAT bit can only be mapped with 4KiB pages.
+    pub fn flags(&self) -> PageEntryFlags {
+        match self {
+            MemoryType::WriteBack => PageEntryFlags::empty(),
+            MemoryType::WriteCombining => PageEntryFlags::WriteThrough,
+            MemoryType::UncachedMinus => PageEntryFlags::CacheDisabled,
+            MemoryType::Uncached => PageEntryFlags::CacheDisabled | PageEntryFlags::WriteThrough,
+            MemoryType::WriteThrough => {
+                PageEntryFlags::PageAttributeTable | PageEntryFlags::CacheDisabled | PageEntryFlags::WriteThrough
+            }
+        }
+    }
+}
+
+// Program the PAT with PAT_LAYOUT. Mappings made with the bootloader layout change type,
+// so this should happen right before switching to our own page table.
+pub fn init_memory_types() {
+    let supported = unsafe {
+        CPU_CONTEXT
+            .info
+            .as_ref()
+            .and_then(|x| x.basic_features.as_ref())
+            .is_some_and(|x| x.flags.contains(BasicFeaturesFlags::PAT))
+    };
+
+    if !supported {
+        warning!("PAT not supported, write-combining will fall back to write-through.");
+        return;
+    }
+    let value = PAT_LAYOUT
+        .iter()
+        .enumerate()
+        .fold(0, |pat, (i, memory_type)| pat | memory_type << (i * 8));
+
+    unsafe { wrmsr(IA32_PAT, value) };
+    flush_tlb_global();
+}
+
+// Make the MMU honour ExecuteDisabled, without NXE bit 63 of the entries is reserved.
+pub fn init_no_execute() {
+    let supported = unsafe {
+        CPU_CONTEXT
+            .info
+            .as_ref()
+            .and_then(|x| x.extended_processor_features.as_ref())
+            .is_some_and(|x| x.flags.contains(ExtendedProcessorFeaturesFlags::NX))
+    };
+
+    if !supported {
+        warning!("NX not supported, pages mapped with ExecuteDisabled will fault.");
+        return;
+    }
+    unsafe { wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_NXE) };
+}
+
+// Past this many pages, reloading CR3 is cheaper than invalidating them one by one.
+const FLUSH_RANGE_MAX_PAGES: usize = 32;
+
 pub fn get_max_level() -> PaginationLevel {
     match unsafe {
         KERNEL_CONTEXT
@@ -34,11 +129,40 @@ pub fn get_max_level() -> PaginationLevel {
     }
 }
 
+// 2MiB pages are always available in long mode, 1GiB ones depend on the CPU.
+pub fn get_max_leaf_level() -> PaginationLevel {
+    let gib_pages = unsafe {
+        CPU_CONTEXT
+            .info
+            .as_ref()
+            .and_then(|x| x.extended_processor_features.as_ref())
+            .is_some_and(|x| x.flags.contains(ExtendedProcessorFeaturesFlags::PAGE1GB))
+    };
+
+    if gib_pages {
+        PaginationLevel::Level3
+    } else {
+        PaginationLevel::Level2
+    }
+}
+
 #[inline]
 pub fn get_page_frame_size() -> usize {
     4096
 }
 
+// Number of virtual address bits translated by the MMU, 48 or 57.
+#[inline]
+pub fn get_virtual_address_bits() -> u32 {
+    get_page_frame_size().trailing_zeros() + 9 * get_max_level() as u32
+}
+
+// Start of the higher half, where the kernel lives.
+#[inline]
+pub fn get_kernel_space_start() -> u64 {
+    !0u64 << (get_virtual_address_bits() - 1)
+}
+
 #[inline]
 pub fn enforce_canonical() -> bool {
     true
@@ -54,6 +178,49 @@ pub fn get_page_table_addr() -> PhysAddr {
     PhysAddr::from(cr3() & ADDRESS_MASK)
 }
 
+#[inline]
+pub fn flush_tlb_page(addr: VirtAddr) {
+    unsafe {
+        invlpg(addr.into());
+    }
+}
+
+pub fn flush_tlb_range(addr: VirtAddr, length: usize) {
+    let pages = length.div_ceil(get_page_frame_size());
+
+    if pages > FLUSH_RANGE_MAX_PAGES {
+        flush_tlb_global();
+        return;
+    }
+    for page in 0..pages {
+        flush_tlb_page(addr + page * get_page_frame_size());
+    }
+}
+
+// Writing CR3 drops every translation except the global ones.
+#[inline]
+pub fn flush_tlb() {
+    write_cr3(cr3());
+}
+
+// Toggling CR4.PGE drops every translation, global ones included.
+pub fn flush_tlb_global() {
+    let cr4 = cr4();
+
+    if cr4 & CR4_PGE == 0 {
+        flush_tlb();
+        return;
+    }
+    write_cr4(cr4 & !CR4_PGE);
+    write_cr4(cr4);
+}
+
+// Ask every other CPU to run the pending shootdown.
+#[inline]
+pub fn send_tlb_shootdown() {
+    lapic::send_ipi_all_but_self(lapic::TLB_SHOOTDOWN_VECTOR);
+}
+
 #[inline]
 pub fn get_page_level_size() -> usize {
     256 * 64
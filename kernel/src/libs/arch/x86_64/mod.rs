@@ -1,10 +1,12 @@
 use seq_macro::seq;
 
 use crate::libs::arch::x86_64::cpu::CpuInfo;
+use crate::libs::generic::memory::paging::tlb;
+use crate::libs::generic::memory::stack::{KernelStack, StackOwner};
 use crate::{
     info,
     libs::arch::x86_64::{
-        gdt::{CPL_RING_0, SegmentSelector},
+        gdt::{CPL_RING_0, GDT_ENTRIES, SegmentSelector, Tss},
         interrupts::idt::{Idt, IdtDescriptor, IdtGateDescriptor, IdtGateDescriptorProperties},
     },
 };
@@ -13,6 +15,7 @@ use core::arch::asm;
 pub mod asm;
 pub mod cpu;
 pub mod gdt;
+pub mod lapic;
 pub mod memory;
 pub mod registers;
 pub mod sse;
@@ -24,22 +27,39 @@ pub mod interrupts {
 }
 
 pub struct CpuContext {
-    gdt: [u64; 5],
+    gdt: [u64; GDT_ENTRIES],
+    tss: Tss,
+    idt: Idt,
     idtr: Option<IdtDescriptor>,
     info: Option<CpuInfo>,
+    // Read once, CPUID is serializing and traps to the hypervisor.
+    apic_id: u32,
+    numa_node: usize,
 }
 
 // NOTE: Yeah buddy you'll have to modify some of that for multi-proc support innit bruv
 pub static mut CPU_CONTEXT: CpuContext = CpuContext {
-    gdt: [0, 0, 0, 0, 0],
+    gdt: [0; GDT_ENTRIES],
+    tss: Tss::new(),
+    idt: [IdtGateDescriptor::empty(); 256],
     idtr: None,
     info: None,
+    apic_id: 0,
+    numa_node: 0,
 };
 
+// Faults that must not run on the stack they interrupted, as it may be the one that overflowed.
+// Page faults stay on the interrupted stack: the CPU resets RSP to the IST slot on every entry,
+// so a nested one would overwrite the outer frame. Hitting a guard page escalates to a double fault.
+const INTERRUPT_STACKS: [(usize, u8); 1] = [
+    (0x8, 1), // Double fault
+];
+const INTERRUPT_STACK_SIZE: usize = 16 * 1024;
+
 fn init_idt() {
-    let mut idtr: [IdtGateDescriptor; 256] = [Default::default(); 256];
+    let idtr: &mut Idt = unsafe { &mut CPU_CONTEXT.idt };
 
-    gdt::load(unsafe { &mut CPU_CONTEXT.gdt });
+    gdt::load(unsafe { &mut CPU_CONTEXT.gdt }, unsafe { &CPU_CONTEXT.tss });
     seq!(N in 0..256 {
         let igtgd: IdtGateDescriptor = IdtGateDescriptor::new(
             crate::arch::internal::interrupts::isr::isr_handler~N as _,
@@ -61,7 +81,7 @@ fn init_idt() {
     unsafe {
         CPU_CONTEXT.idtr = Some(IdtDescriptor {
             size: (size_of::<IdtGateDescriptor>() * 256) as u16 - 1,
-            idt_offset: (&idtr) as *const Idt,
+            idt_offset: (&CPU_CONTEXT.idt) as *const Idt,
         });
         interrupts::idt::load(CPU_CONTEXT.idtr.as_ref().unwrap());
         asm!("sti");
@@ -70,16 +90,22 @@ fn init_idt() {
 
 #[allow(static_mut_refs)]
 pub unsafe fn init() {
-    gdt::load(unsafe { &mut CPU_CONTEXT.gdt });
+    gdt::load(unsafe { &mut CPU_CONTEXT.gdt }, unsafe { &CPU_CONTEXT.tss });
     init_idt();
 
     unsafe {
         CPU_CONTEXT.info = Some(CpuInfo::new());
+        CPU_CONTEXT.apic_id = cpu::apic_id();
         CPU_CONTEXT
             .info
             .as_mut()
             .unwrap()
             .request(cpu::CpuIdRequest::BasicFeatures);
+        CPU_CONTEXT
+            .info
+            .as_mut()
+            .unwrap()
+            .request(cpu::CpuIdRequest::ExtendedProcessorFeatures);
         info!(
             "APIC supported: {}",
             CPU_CONTEXT
@@ -100,6 +126,45 @@ pub unsafe fn init() {
     }
 }
 
+#[inline]
+pub fn apic_id() -> u32 {
+    unsafe { CPU_CONTEXT.apic_id }
+}
+
+#[inline]
+pub fn numa_node() -> usize {
+    unsafe { CPU_CONTEXT.numa_node }
+}
+
+pub fn set_numa_node(node: usize) {
+    unsafe { CPU_CONTEXT.numa_node = node };
+}
+
+// Second stage, needs the kernel address space.
+pub fn late_init() {
+    init_interrupt_stacks();
+    lapic::init();
+    // The other CPUs are left parked by the bootloader, they don't take part in shootdowns.
+    tlb::cpu_online();
+}
+
+// Give the faults in INTERRUPT_STACKS their own guarded stacks.
+fn init_interrupt_stacks() {
+    for (vector, ist) in INTERRUPT_STACKS {
+        let stack = KernelStack::allocate(INTERRUPT_STACK_SIZE, StackOwner::Interrupt(ist))
+            .expect("Failed to allocate an interrupt stack.");
+
+        unsafe {
+            let mut stacks = CPU_CONTEXT.tss.ist;
+
+            stacks[ist as usize - 1] = stack.leak().into();
+            CPU_CONTEXT.tss.ist = stacks;
+            CPU_CONTEXT.idt[vector].ist_offset = ist;
+        }
+    }
+    info!("Interrupt stacks allocated");
+}
+
 // Note: This is not in the generic section as each architecture has a dedicated linker script
 unsafe extern "C" {
     pub unsafe static LD_TEXT_START: u8;
@@ -5,7 +5,6 @@ pub fn init() -> Result<(), ()> {
         CPU_CONTEXT.info.as_ref().ok_or(())?
             .basic_features.as_ref().ok_or(())?
                 .flags.contains(BasicFeaturesFlags::SSE) } {
-        // TODO: liballoc is currently built using SSE instructions, so we cannot fallback to emulation yet.
         warning!("SSE not supported on this CPU, defaulting to emulation mode for floating point operations.");
         return Err(());
     }
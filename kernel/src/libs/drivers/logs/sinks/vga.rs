@@ -7,14 +7,22 @@ static FONT_DATA: &[u8] = include_bytes!("../../../../../resources/fonts/zap-lig
 // This is a naive, non-optimized VGA text mode driver.
 // It is only intended to be used for displaying text when no memory management is available.
 // It doesn't support scaling yet, since the goal is to just hand over control to a better log sink when possible
-pub struct VgaSink<'a> {
-    framebuffer: &'a Framebuffer<'a>,
+pub struct VgaSink {
+    framebuffer: FramebufferInfo,
     font: PsfFont,
     cursor_pos: (u32, u32),
     foreground_color: u32,
     background_color: u32
 }
 
+// The Limine framebuffer structure lives in bootloader memory, which gets reclaimed.
+// We keep our own copy of what we need instead.
+struct FramebufferInfo {
+    addr: *mut u8,
+    width: u64,
+    height: u64,
+}
+
 #[allow(dead_code)]
 fn xterm_code_to_color(code: u8) -> u32 {
     let mut r: u8 = 0;
@@ -40,8 +48,8 @@ fn xterm_code_to_color(code: u8) -> u32 {
     return 0xFF000000 | ((r as u32) << 16) | ((g as u32) << 8) | b as u32;
 }
 
-impl<'a> VgaSink<'a> {
-    pub fn clear(framebuffer: &'a Framebuffer<'a>, color: u32) {
+impl VgaSink {
+    pub fn clear(framebuffer: &Framebuffer, color: u32) {
         for i in (framebuffer.width() * 50)..framebuffer.width() * framebuffer.height() {
             unsafe {
                 framebuffer
@@ -55,18 +63,18 @@ impl<'a> VgaSink<'a> {
 
     pub fn scroll(&mut self) {
         unsafe {
-            let fb_raw: *mut u32 = self.framebuffer.addr().cast::<u32>();
+            let fb_raw: *mut u32 = self.framebuffer.addr.cast::<u32>();
             let fb_new_start: *mut u32 = self
                 .framebuffer
-                .addr()
+                .addr
                 .cast::<u32>()
-                .add(self.framebuffer.width() as usize * self.font.glyph_size.1 as usize);
+                .add(self.framebuffer.width as usize * self.font.glyph_size.1 as usize);
 
             core::ptr::copy(
                 fb_new_start,
                 fb_raw,
-                self.framebuffer.width() as usize
-                    * (self.framebuffer.height() as usize - self.font.glyph_size.1 as usize),
+                self.framebuffer.width as usize
+                    * (self.framebuffer.height as usize - self.font.glyph_size.1 as usize),
             );
             self.clear_line(self.cursor_pos.1 as usize);
         };
@@ -74,15 +82,15 @@ impl<'a> VgaSink<'a> {
 
     pub fn clear_line(&mut self, line: usize) {
         unsafe {
-            let fb_line: *mut u32 = self.framebuffer.addr().cast::<u32>().add(
-                self.framebuffer.width() as usize
+            let fb_line: *mut u32 = self.framebuffer.addr.cast::<u32>().add(
+                self.framebuffer.width as usize
                     * (line as usize * self.font.glyph_size.1 as usize),
             );
 
             core::ptr::write_bytes(
                 fb_line,
                 0x0,
-                self.framebuffer.width() as usize * self.font.glyph_size.1 as usize,
+                self.framebuffer.width as usize * self.font.glyph_size.1 as usize,
             );
         }
     }
@@ -93,7 +101,7 @@ impl<'a> VgaSink<'a> {
 
     }
 
-    pub fn new(framebuffer: &'a Framebuffer<'a>) -> Self {
+    pub fn new(framebuffer: &Framebuffer) -> Self {
         let font = PsfFont::parse(FONT_DATA);
 
         if font.is_none() {
@@ -101,7 +109,11 @@ impl<'a> VgaSink<'a> {
             panic!("Failed to parse font data");
         }
         Self {
-            framebuffer,
+            framebuffer: FramebufferInfo {
+                addr: framebuffer.addr(),
+                width: framebuffer.width(),
+                height: framebuffer.height(),
+            },
             font: font.unwrap(),
             cursor_pos: (0, 0),
             background_color: 0x0,
@@ -110,11 +122,11 @@ impl<'a> VgaSink<'a> {
     }
 }
 
-impl<'a> Sink for VgaSink<'a> {
+impl Sink for VgaSink {
     fn putchar(&mut self, c: char) {
         if c == '\n' {
             if self.cursor_pos.1 as u64 * self.font.glyph_size.1 as u64
-                >= (self.framebuffer.height() - (self.font.glyph_size.1 as u64))
+                >= (self.framebuffer.height - (self.font.glyph_size.1 as u64))
             {
                 self.scroll();
                 self.cursor_pos.0 = 0;
@@ -148,21 +160,21 @@ impl<'a> Sink for VgaSink<'a> {
 
                 unsafe {
                     self.framebuffer
-                        .addr()
+                        .addr
                         .cast::<u32>()
-                        .add((absolute_y * self.framebuffer.width() + absolute_x) as usize)
+                        .add((absolute_y * self.framebuffer.width + absolute_x) as usize)
                         .write(pixel_color);
                 };
             }
         }
         self.cursor_pos.0 += 1;
 
-        if self.cursor_pos.0 as u64 * self.font.glyph_size.0 as u64 >= self.framebuffer.width() {
+        if self.cursor_pos.0 as u64 * self.font.glyph_size.0 as u64 >= self.framebuffer.width {
             self.cursor_pos.0 = 0;
             self.cursor_pos.1 += 1;
 
             if self.cursor_pos.1 as u64 * self.font.glyph_size.1 as u64
-                >= (self.framebuffer.height() - (self.font.glyph_size.1 as u64))
+                >= (self.framebuffer.height - (self.font.glyph_size.1 as u64))
             {
                 self.scroll();
                 self.cursor_pos.0 = 0;
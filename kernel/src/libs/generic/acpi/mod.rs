@@ -0,0 +1,113 @@
+use crate::libs::generic::memory::address::PhysAddr;
+
+pub mod slit;
+pub mod srat;
+
+// Every system description table starts with this header.
+pub const SDT_HEADER_SIZE: usize = 36;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+// Size of the ACPI 1.0 RSDP, the checksum only covers these bytes.
+const RSDP_V1_SIZE: usize = 20;
+const RSDP_V2_SIZE: usize = 36;
+
+#[inline]
+pub fn read_u8(bytes: &[u8], offset: usize) -> u8 {
+    bytes[offset]
+}
+
+#[inline]
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+pub fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, x| sum.wrapping_add(*x)) == 0
+}
+
+// Firmware memory read through the HHDM.
+unsafe fn physical_slice(addr: PhysAddr, length: usize) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(addr.as_hhdm().as_mut_ptr::<u8>(), length) }
+}
+
+// Root of the ACPI tables, found through the RSDP given by the bootloader.
+pub struct AcpiTables {
+    root: &'static [u8],
+    // The XSDT holds 64 bit pointers, the RSDT 32 bit ones.
+    entry_size: usize,
+}
+
+impl AcpiTables {
+    // `rsdp` must be the physical address of the RSDP, with the tables mapped in the HHDM.
+    pub unsafe fn new(rsdp: PhysAddr) -> Option<Self> {
+        let header = unsafe { physical_slice(rsdp, RSDP_V1_SIZE) };
+
+        if &header[0..8] != RSDP_SIGNATURE || !checksum(header) {
+            return None;
+        }
+
+        let (root, entry_size) = if read_u8(header, 15) >= 2 {
+            let extended = unsafe { physical_slice(rsdp, RSDP_V2_SIZE) };
+
+            if !checksum(extended) {
+                return None;
+            }
+            (read_u64(extended, 24), 8)
+        } else {
+            (read_u32(header, 16) as u64, 4)
+        };
+
+        Some(Self {
+            root: unsafe { AcpiTables::table(PhysAddr::from(root))? },
+            entry_size,
+        })
+    }
+
+    // Whole table at `addr`, if its checksum is valid.
+    unsafe fn table(addr: PhysAddr) -> Option<&'static [u8]> {
+        let header = unsafe { physical_slice(addr, SDT_HEADER_SIZE) };
+        let table = unsafe { physical_slice(addr, read_u32(header, 4) as usize) };
+
+        if table.len() < SDT_HEADER_SIZE || !checksum(table) {
+            return None;
+        }
+        Some(table)
+    }
+
+    pub fn find(&self, signature: &[u8; 4]) -> Option<&'static [u8]> {
+        self.root[SDT_HEADER_SIZE..]
+            .chunks_exact(self.entry_size)
+            .map(|entry| match self.entry_size {
+                8 => read_u64(entry, 0),
+                _ => read_u32(entry, 0) as u64,
+            })
+            .filter_map(|addr| unsafe { AcpiTables::table(PhysAddr::from(addr)) })
+            .find(|table| &table[0..4] == signature)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    extern crate alloc;
+
+    use alloc::vec::Vec;
+
+    use crate::libs::generic::acpi::SDT_HEADER_SIZE;
+
+    // Table with a valid header around `body`, `body` starting right after the header.
+    pub fn make_table(signature: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut table = Vec::from(*signature);
+        let length = (SDT_HEADER_SIZE + body.len()) as u32;
+
+        table.extend_from_slice(&length.to_le_bytes());
+        table.resize(SDT_HEADER_SIZE, 0);
+        table.extend_from_slice(body);
+        table[9] = 0u8.wrapping_sub(table.iter().fold(0u8, |sum, x| sum.wrapping_add(*x)));
+        table
+    }
+}
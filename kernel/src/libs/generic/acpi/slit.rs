@@ -0,0 +1,64 @@
+use crate::libs::generic::{
+    acpi::{read_u64, SDT_HEADER_SIZE},
+    memory::numa::NumaTopology,
+};
+
+// The distance matrix follows the number of localities.
+const MATRIX_OFFSET: usize = SDT_HEADER_SIZE + 8;
+
+// Distance between unreachable localities.
+const UNREACHABLE: u8 = 0xFF;
+
+// Set the distances between the nodes of `topology` from the System Locality Information Table.
+// Localities are proximity domains, so the SRAT must have been parsed first.
+pub fn parse(table: &[u8], topology: &mut NumaTopology) {
+    if table.len() < MATRIX_OFFSET {
+        return;
+    }
+
+    let localities = read_u64(table, SDT_HEADER_SIZE) as usize;
+
+    if localities.checked_mul(localities).is_none_or(|x| MATRIX_OFFSET + x > table.len()) {
+        return;
+    }
+    for from in 0..localities {
+        for to in 0..localities {
+            let distance = table[MATRIX_OFFSET + from * localities + to];
+
+            if distance != UNREACHABLE {
+                topology.set_distance(from as u32, to as u32, distance);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec::Vec;
+
+    use crate::libs::generic::{
+        acpi::{slit, tests::make_table},
+        memory::numa::{NumaTopology, LOCAL_DISTANCE, REMOTE_DISTANCE},
+    };
+
+    #[test]
+    fn parse_distances() {
+        let mut topology = NumaTopology::uniform();
+        let mut body = Vec::from(3u64.to_le_bytes());
+
+        topology.clear();
+        topology.add_memory(0, 0x1000_0000, 0);
+        topology.add_memory(0x1000_0000, 0x1000_0000, 2);
+        topology.add_memory(0x2000_0000, 0x1000_0000, 1);
+        body.extend_from_slice(&[10, 0xFF, 31, 0xFF, 10, 17, 31, 17, 10]);
+        slit::parse(&make_table(b"SLIT", &body), &mut topology);
+
+        // Domain 2 is node 1 and domain 1 is node 2, in the order the SRAT listed them.
+        assert_eq!(topology.distance(0, 1), 31);
+        assert_eq!(topology.distance(1, 2), 17);
+        assert_eq!(topology.distance(0, 2), REMOTE_DISTANCE);
+        assert_eq!(topology.distance(2, 2), LOCAL_DISTANCE);
+    }
+}
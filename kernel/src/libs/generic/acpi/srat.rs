@@ -0,0 +1,93 @@
+use crate::libs::generic::{
+    acpi::{read_u32, read_u64, read_u8, SDT_HEADER_SIZE},
+    memory::numa::NumaTopology,
+};
+
+// The affinity structures start after the header and 12 reserved bytes.
+const ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 12;
+
+const PROCESSOR_AFFINITY: u8 = 0;
+const MEMORY_AFFINITY: u8 = 1;
+const X2APIC_AFFINITY: u8 = 2;
+
+// Bit 0 of the flags of every affinity structure, disabled entries must be ignored.
+const ENABLED: u32 = 1;
+
+// Add the CPUs and memory ranges of the System Resource Affinity Table to `topology`.
+pub fn parse(table: &[u8], topology: &mut NumaTopology) {
+    let mut offset = ENTRIES_OFFSET;
+
+    topology.clear();
+    while offset + 2 <= table.len() {
+        let kind = read_u8(table, offset);
+        let length = read_u8(table, offset + 1) as usize;
+
+        if length < 2 || offset + length > table.len() {
+            break;
+        }
+
+        let entry = &table[offset..offset + length];
+
+        match kind {
+            PROCESSOR_AFFINITY if length >= 16 && read_u32(entry, 4) & ENABLED != 0 => {
+                // The proximity domain is split, low 8 bits first then the 24 high bits.
+                let domain = read_u8(entry, 2) as u32 | (read_u32(entry, 8) & 0xFFFF_FF00);
+
+                topology.add_cpu(read_u8(entry, 3) as u32, domain);
+            }
+            MEMORY_AFFINITY if length >= 40 && read_u32(entry, 28) & ENABLED != 0 => {
+                topology.add_memory(read_u64(entry, 8), read_u64(entry, 16), read_u32(entry, 2));
+            }
+            X2APIC_AFFINITY if length >= 24 && read_u32(entry, 12) & ENABLED != 0 => {
+                topology.add_cpu(read_u32(entry, 8), read_u32(entry, 4));
+            }
+            _ => (),
+        }
+        offset += length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec::Vec;
+
+    use crate::libs::generic::{
+        acpi::{srat, tests::make_table},
+        memory::numa::NumaTopology,
+    };
+
+    fn memory_affinity(domain: u32, base: u64, length: u64, flags: u32) -> Vec<u8> {
+        let mut entry = Vec::from([1u8, 40]);
+
+        entry.extend_from_slice(&domain.to_le_bytes());
+        entry.extend_from_slice(&[0; 2]);
+        entry.extend_from_slice(&base.to_le_bytes());
+        entry.extend_from_slice(&length.to_le_bytes());
+        entry.extend_from_slice(&[0; 4]);
+        entry.extend_from_slice(&flags.to_le_bytes());
+        entry.extend_from_slice(&[0; 8]);
+        entry
+    }
+
+    #[test]
+    fn parse_affinities() {
+        let mut body = Vec::from([0u8; 12]);
+        let mut topology = NumaTopology::uniform();
+
+        // Processor with APIC ID 3 in domain 0x100 (split between the low byte and the high bytes).
+        body.extend_from_slice(&[0, 16, 0x00, 3, 1, 0, 0, 0, 0, 0x01, 0, 0, 0, 0, 0, 0]);
+        body.extend_from_slice(&memory_affinity(0x100, 0, 0x4000_0000, 1));
+        body.extend_from_slice(&memory_affinity(2, 0x4000_0000, 0x4000_0000, 1));
+        // Disabled entries are skipped.
+        body.extend_from_slice(&memory_affinity(5, 0x8000_0000, 0x4000_0000, 0));
+        srat::parse(&make_table(b"SRAT", &body), &mut topology);
+
+        assert_eq!(topology.node_count(), 2);
+        assert_eq!(topology.domain(0), 0x100);
+        assert_eq!(topology.node_of_cpu(3), 0);
+        assert_eq!(topology.node_of_address(0x4000_1000), 1);
+        assert_eq!(topology.node_of_domain(5), None);
+    }
+}
@@ -4,10 +4,10 @@ use crate::{
     debug, libs::{arch, generic::memory::paging::PaginationLevel}, KERNEL_CONTEXT
 };
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct VirtAddr(u64);
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct PhysAddr(u64);
 
 #[derive(Debug)]
@@ -23,6 +23,44 @@ impl VirtAddr {
         (self.0 >> 12 >> ((level as u64 - 1) * 9)) & 0x1FF
     }
 
+    // Addresses must be sign extended from the highest bit the MMU translates.
+    #[inline]
+    pub fn is_canonical(value: u64) -> bool {
+        VirtAddr::canonicalize(value, arch::paging::get_virtual_address_bits()).0 == value
+    }
+
+    // Sign extend an address built from page table indices, from its `bits` lowest bits.
+    #[inline]
+    pub fn canonicalize(value: u64, bits: u32) -> VirtAddr {
+        let shift = 64 - bits;
+
+        VirtAddr((((value << shift) as i64) >> shift) as u64)
+    }
+
+    #[inline]
+    pub fn align_down(&self, alignment: usize) -> VirtAddr {
+        VirtAddr(self.0 & !(alignment as u64 - 1))
+    }
+
+    // Aligning the last page of the address space up wraps to 0, which is still canonical.
+    #[inline]
+    pub fn align_up(&self, alignment: usize) -> VirtAddr {
+        VirtAddr(self.0.wrapping_add(alignment as u64 - 1) & !(alignment as u64 - 1))
+    }
+
+    #[inline]
+    pub fn is_aligned(&self, alignment: usize) -> bool {
+        self.0 & (alignment as u64 - 1) == 0
+    }
+
+    pub fn checked_add(&self, rhs: usize) -> Option<VirtAddr> {
+        VirtAddr::try_from(self.0.checked_add(rhs as u64)?).ok()
+    }
+
+    pub fn checked_sub(&self, rhs: usize) -> Option<VirtAddr> {
+        VirtAddr::try_from(self.0.checked_sub(rhs as u64)?).ok()
+    }
+
     pub unsafe fn as_ptr<T>(&self) -> *const T {
         self.0 as *const T
     }
@@ -58,10 +96,10 @@ impl TryFrom<u64> for VirtAddr {
     type Error = NonCanonicalAddress;
 
     fn try_from(value: u64) -> Result<Self, Self::Error> {
-        if arch::paging::enforce_canonical() {
-            // TODO: Check for canonical
+        if arch::paging::enforce_canonical() && !VirtAddr::is_canonical(value) {
+            return Err(NonCanonicalAddress());
         }
-        Ok(VirtAddr { 0: value })
+        Ok(VirtAddr(value))
     }
 }
 
@@ -86,16 +124,43 @@ impl From<VirtAddr> for usize {
 impl PhysAddr {
     pub fn as_hhdm(&self) -> VirtAddr {
         unsafe {
-            VirtAddr {
-                0: self.0 | KERNEL_CONTEXT.boot_info.hhdm,
-            }
+            VirtAddr(self.0 | KERNEL_CONTEXT.boot_info.hhdm)
+        }
+    }
+
+    #[inline]
+    pub fn align_down(&self, alignment: usize) -> PhysAddr {
+        PhysAddr(self.0 & !(alignment as u64 - 1))
+    }
+
+    #[inline]
+    pub fn align_up(&self, alignment: usize) -> PhysAddr {
+        PhysAddr(self.0.wrapping_add(alignment as u64 - 1) & !(alignment as u64 - 1))
+    }
+
+    #[inline]
+    pub fn is_aligned(&self, alignment: usize) -> bool {
+        self.0 & (alignment as u64 - 1) == 0
+    }
+
+    pub fn checked_add(&self, rhs: usize) -> Option<PhysAddr> {
+        Some(PhysAddr(self.0.checked_add(rhs as u64)?))
+    }
+
+    pub fn checked_sub(&self, rhs: usize) -> Option<PhysAddr> {
+        Some(PhysAddr(self.0.checked_sub(rhs as u64)?))
+    }
+
+    pub fn from_hhdm(virt_addr: VirtAddr) -> PhysAddr {
+        unsafe {
+            PhysAddr(virt_addr.0 - KERNEL_CONTEXT.boot_info.hhdm)
         }
     }
 }
 
 impl From<u64> for PhysAddr {
     fn from(value: u64) -> Self {
-        Self { 0: value }
+        Self(value)
     }
 }
 
@@ -115,7 +180,7 @@ impl Add<usize> for PhysAddr {
     type Output = PhysAddr;
 
     fn add(self, rhs: usize) -> Self::Output {
-        PhysAddr { 0: self.0.wrapping_add(rhs as u64) }
+        PhysAddr(self.0.wrapping_add(rhs as u64))
     }
 }
 
@@ -123,33 +188,237 @@ impl Add<usize> for VirtAddr {
     type Output = VirtAddr;
 
     fn add(self, rhs: usize) -> Self::Output {
-        VirtAddr { 0: self.0.wrapping_add(rhs as u64) }
+        VirtAddr(self.0.wrapping_add(rhs as u64))
     }
 }
 
+// Distance between two addresses, underflowing is a bug.
 impl Sub<VirtAddr> for VirtAddr {
-    type Output = VirtAddr;
+    type Output = usize;
 
     fn sub(self, rhs: VirtAddr) -> Self::Output {
-        VirtAddr { 0: self.0.wrapping_sub(rhs.0) }
+        (self.0 - rhs.0) as usize
+    }
+}
+
+impl Sub<PhysAddr> for PhysAddr {
+    type Output = usize;
+
+    fn sub(self, rhs: PhysAddr) -> Self::Output {
+        (self.0 - rhs.0) as usize
+    }
+}
+
+impl Sub<usize> for VirtAddr {
+    type Output = VirtAddr;
+
+    fn sub(self, rhs: usize) -> Self::Output {
+        VirtAddr(self.0.wrapping_sub(rhs as u64))
+    }
+}
+
+impl Sub<usize> for PhysAddr {
+    type Output = PhysAddr;
+
+    fn sub(self, rhs: usize) -> Self::Output {
+        PhysAddr(self.0.wrapping_sub(rhs as u64))
+    }
+}
+
+// A virtual page, always aligned on the page frame size.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Page(VirtAddr);
+
+// A physical page frame, always aligned on the page frame size.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Frame(PhysAddr);
+
+impl Page {
+    pub fn containing(addr: VirtAddr) -> Page {
+        Page(addr.align_down(arch::paging::get_page_frame_size()))
+    }
+
+    // None if the address is not the start of a page.
+    pub fn from_start(addr: VirtAddr) -> Option<Page> {
+        addr.is_aligned(arch::paging::get_page_frame_size()).then_some(Page(addr))
+    }
+
+    #[inline]
+    pub fn start(&self) -> VirtAddr {
+        self.0
+    }
+
+    pub fn checked_add(&self, count: usize) -> Option<Page> {
+        Some(Page(self.0.checked_add(count.checked_mul(arch::paging::get_page_frame_size())?)?))
+    }
+}
+
+impl Frame {
+    pub fn containing(addr: PhysAddr) -> Frame {
+        Frame(addr.align_down(arch::paging::get_page_frame_size()))
+    }
+
+    // None if the address is not the start of a frame.
+    pub fn from_start(addr: PhysAddr) -> Option<Frame> {
+        addr.is_aligned(arch::paging::get_page_frame_size()).then_some(Frame(addr))
+    }
+
+    #[inline]
+    pub fn start(&self) -> PhysAddr {
+        self.0
+    }
+
+    #[inline]
+    pub fn number(&self) -> usize {
+        (self.0.0 / arch::paging::get_page_frame_size() as u64) as usize
+    }
+
+    pub fn checked_add(&self, count: usize) -> Option<Frame> {
+        Some(Frame(self.0.checked_add(count.checked_mul(arch::paging::get_page_frame_size())?)?))
+    }
+}
+
+// Half-open range of pages, iterating over it yields every page in order.
+#[derive(Copy, Clone, Debug)]
+pub struct PageRange {
+    pub start: Page,
+    pub end: Page,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FrameRange {
+    pub start: Frame,
+    pub end: Frame,
+}
+
+impl PageRange {
+    pub fn new(start: Page, end: Page) -> Self {
+        Self { start, end }
+    }
+
+    // Every page touched by `length` bytes starting at `addr`, None if the last page ends past the address space.
+    pub fn covering(addr: VirtAddr, length: usize) -> Option<Self> {
+        let unaligned = addr.checked_add(length)?;
+        let end = unaligned.align_up(arch::paging::get_page_frame_size());
+
+        (end >= unaligned).then_some(Self { start: Page::containing(addr), end: Page(end) })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        (self.end.0.0.saturating_sub(self.start.0.0) / arch::paging::get_page_frame_size() as u64) as usize
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Iterator for PageRange {
+    type Item = Page;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let page = self.start;
+
+        self.start = Page(self.start.0 + arch::paging::get_page_frame_size());
+        Some(page)
+    }
+}
+
+impl FrameRange {
+    pub fn new(start: Frame, end: Frame) -> Self {
+        Self { start, end }
+    }
+
+    // Every frame touched by `length` bytes starting at `addr`, None if the last frame ends past the address space.
+    pub fn covering(addr: PhysAddr, length: usize) -> Option<Self> {
+        let unaligned = addr.checked_add(length)?;
+        let end = unaligned.align_up(arch::paging::get_page_frame_size());
+
+        (end >= unaligned).then_some(Self { start: Frame::containing(addr), end: Frame(end) })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        (self.end.0.0.saturating_sub(self.start.0.0) / arch::paging::get_page_frame_size() as u64) as usize
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Iterator for FrameRange {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let frame = self.start;
+
+        self.start = Frame(self.start.0 + arch::paging::get_page_frame_size());
+        Some(frame)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::libs::generic::memory::{address::VirtAddr, paging::PaginationLevel};
+    use limine::paging::Mode;
+
+    use crate::{
+        libs::generic::memory::{
+            address::{FrameRange, Page, PageRange, PhysAddr, VirtAddr},
+            paging::PaginationLevel,
+        },
+        KERNEL_CONTEXT,
+    };
 
     #[test]
     fn virtaddr_get_offsets() {
-        let addr: VirtAddr = VirtAddr {
-            0: 0x1BDAFE7EEFBE5CF,
-        };
+        let addr: VirtAddr = VirtAddr(0x1BDAFE7EEFBE5CF);
 
         assert_eq!(addr.get_level_offset(PaginationLevel::Physical), 0x5CF);
         assert_eq!(addr.get_level_offset(PaginationLevel::Level1), 0x1BE);
         assert_eq!(addr.get_level_offset(PaginationLevel::Level2), 0x177);
         assert_eq!(addr.get_level_offset(PaginationLevel::Level3), 0x19F);
         assert_eq!(addr.get_level_offset(PaginationLevel::Level4), 0x15F);
-        assert_eq!(addr.get_level_offset(PaginationLevel::Level4), 0x1BD);
+        assert_eq!(addr.get_level_offset(PaginationLevel::Level5), 0x1BD);
+    }
+
+    #[test]
+    fn virtaddr_canonical() {
+        unsafe { KERNEL_CONTEXT.boot_info.paging_level = Some(Mode::FOUR_LEVEL) };
+
+        assert!(VirtAddr::try_from(0x7FFF_FFFF_F000).is_ok());
+        assert!(VirtAddr::try_from(0x8000_0000_0000).is_err());
+        assert!(VirtAddr::try_from(0xFFFF_8000_0000_0000).is_ok());
+        assert!(VirtAddr::try_from(0xFF00_0000_0000_0000).is_err());
+        assert!(VirtAddr::try_from(0x7FFF_FFFF_F000).unwrap().checked_add(0x1000).is_none());
+    }
+
+    #[test]
+    fn page_range_covering() {
+        unsafe { KERNEL_CONTEXT.boot_info.paging_level = Some(Mode::FOUR_LEVEL) };
+        let range = PageRange::covering(VirtAddr::try_from(0x1FFF).unwrap(), 2).unwrap();
+
+        assert_eq!(range.len(), 2);
+        assert_eq!(range.map(|x| x.start()).collect::<alloc::vec::Vec<_>>(), [
+            VirtAddr::try_from(0x1000).unwrap(),
+            VirtAddr::try_from(0x2000).unwrap(),
+        ]);
+        assert!(Page::from_start(VirtAddr::try_from(0x1800).unwrap()).is_none());
+        // The last page ends past the top of the address space.
+        assert!(PageRange::covering(VirtAddr::try_from(0xFFFF_FFFF_FFFF_F800).unwrap(), 0x100).is_none());
+        assert!(FrameRange::covering(PhysAddr::from(u64::MAX - 0xFF), 0x10).is_none());
+        assert_eq!(PhysAddr::from(u64::MAX - 0xFF).align_up(0x1000), PhysAddr::from(0));
+        assert_eq!(PhysAddr::from(0x3000) - PhysAddr::from(0x1000), 0x2000);
     }
 }
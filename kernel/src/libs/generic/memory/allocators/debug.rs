@@ -0,0 +1,277 @@
+use core::{alloc::Layout, ptr::null_mut};
+
+use crate::{_log, debug, libs::generic::memory::allocators::heap::Heap};
+#[cfg(feature = "kasan")]
+use crate::libs::generic::memory::kasan;
+
+// Bytes checked on both sides of every allocation.
+const RED_ZONE_SIZE: usize = 16;
+
+const RED_ZONE_BYTE: u8 = 0xFD;
+// Fresh allocations are filled with this, so reads of uninitialized memory stand out.
+const UNINIT_BYTE: u8 = 0xCD;
+// Freed allocations are filled with this, so use after free stands out.
+const POISON_BYTE: u8 = 0xDD;
+
+const MAGIC_ALLOCATED: u64 = 0xA110_CA7E_DA11_0CED;
+const MAGIC_FREED: u64 = 0xF8EE_DF8E_EDF8_EED0;
+
+// Live allocations recorded for leak tracking, allocations past this are still checked but not listed.
+const MAX_TRACKED: usize = 4096;
+// Last freed allocations, kept to tell where memory used after free came from.
+const RECENTLY_FREED: usize = 256;
+
+// Free slot markers in the allocation table.
+const EMPTY: usize = 0;
+const TOMBSTONE: usize = usize::MAX;
+
+// Written at the start of every block, before the front red zone.
+#[repr(C)]
+struct Header {
+    // Overwritten by the slab free list once the block is freed.
+    _free_list: [u64; 2],
+    magic: u64,
+    size: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    ptr: usize,
+    size: usize,
+    caller: usize,
+}
+
+// Wraps the heap with red zones around allocations, poisoning of freed memory,
+// double free detection and a table of live allocations.
+// Blocks are laid out as [Header | front red zone | object | back red zone].
+pub struct DebugHeap {
+    heap: Heap,
+    records: [Record; MAX_TRACKED],
+    live: usize,
+    untracked: usize,
+    freed: [Record; RECENTLY_FREED],
+    next_freed: usize,
+}
+
+impl Default for DebugHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DebugHeap {
+    pub const fn new() -> Self {
+        Self {
+            heap: Heap::new(),
+            records: [Record { ptr: EMPTY, size: 0, caller: 0 }; MAX_TRACKED],
+            live: 0,
+            untracked: 0,
+            freed: [Record { ptr: EMPTY, size: 0, caller: 0 }; RECENTLY_FREED],
+            next_freed: 0,
+        }
+    }
+
+    // Offset of the object in its block, keeping the alignment it asked for.
+    #[inline]
+    fn front_size(layout: &Layout) -> usize {
+        (size_of::<Header>() + RED_ZONE_SIZE).next_multiple_of(layout.align())
+    }
+
+    #[inline]
+    pub fn block_layout(layout: &Layout) -> Layout {
+        Layout::from_size_align(DebugHeap::front_size(layout) + layout.size() + RED_ZONE_SIZE, layout.align())
+            .expect("Heap allocation too large for the debug heap.")
+    }
+
+    #[inline]
+    fn slot(ptr: usize) -> usize {
+        (ptr >> 4) % MAX_TRACKED
+    }
+
+    pub fn allocate(&mut self, layout: Layout, caller: usize) -> *mut u8 {
+        let block = self.heap.allocate(DebugHeap::block_layout(&layout));
+
+        if block.is_null() {
+            return null_mut();
+        }
+        unsafe { self.prepare(block, layout, caller) }
+    }
+
+    // Safety: `ptr` must come from allocate or prepare with the same layout.
+    pub unsafe fn free(&mut self, ptr: *mut u8, layout: Layout) {
+        let block = unsafe { self.release(ptr, layout) };
+
+        self.heap.free(block, DebugHeap::block_layout(&layout));
+    }
+
+    // Set up the header and red zones of a block of block_layout(layout) bytes, returns the object.
+    // Blocks too large for the slabs come from heap::allocate_large, which can't run under the heap lock.
+    // Safety: `block` must be a fresh heap block of block_layout(layout) bytes.
+    pub unsafe fn prepare(&mut self, block: *mut u8, layout: Layout, caller: usize) -> *mut u8 {
+        #[cfg(feature = "kasan")]
+        let _suspended = kasan::suspend();
+        let front = DebugHeap::front_size(&layout);
+
+        unsafe {
+            let ptr = block.add(front);
+
+            (block as *mut Header).write(Header {
+                _free_list: [0; 2],
+                magic: MAGIC_ALLOCATED,
+                size: layout.size(),
+            });
+            core::ptr::write_bytes(block.add(size_of::<Header>()), RED_ZONE_BYTE, front - size_of::<Header>());
+            core::ptr::write_bytes(ptr, UNINIT_BYTE, layout.size());
+            core::ptr::write_bytes(ptr.add(layout.size()), RED_ZONE_BYTE, RED_ZONE_SIZE);
+            // The back red zone starts in the object's last granule, unpoisoning marks it partially accessible.
+            #[cfg(feature = "kasan")]
+            {
+                kasan::poison(block, front, kasan::HEAP_REDZONE);
+                kasan::unpoison(ptr, layout.size());
+                kasan::poison(ptr.add(layout.size().next_multiple_of(8)), RED_ZONE_SIZE, kasan::HEAP_REDZONE);
+            }
+            self.track(ptr as usize, layout.size(), caller);
+            ptr
+        }
+    }
+
+    // Check and poison an object, returns its block to give back to the heap.
+    // Safety: `ptr` must come from allocate or prepare with the same layout.
+    pub unsafe fn release(&mut self, ptr: *mut u8, layout: Layout) -> *mut u8 {
+        #[cfg(feature = "kasan")]
+        let _suspended = kasan::suspend();
+        let front = DebugHeap::front_size(&layout);
+        let record = self.untrack(ptr as usize);
+
+        unsafe {
+            let block = ptr.sub(front);
+            let header = &mut *(block as *mut Header);
+
+            match header.magic {
+                MAGIC_ALLOCATED => (),
+                MAGIC_FREED => panic!("Double free of heap allocation {:p} ({} bytes)", ptr, layout.size()),
+                _ => panic!("Freeing {:p} which isn't a heap allocation, or its header was overwritten", ptr),
+            }
+            assert!(
+                header.size == layout.size(),
+                "Heap allocation {:p} freed with size {} but allocated with {}",
+                ptr,
+                layout.size(),
+                header.size
+            );
+
+            let front_zone = core::slice::from_raw_parts(block.add(size_of::<Header>()), front - size_of::<Header>());
+            let back_zone = core::slice::from_raw_parts(ptr.add(layout.size()), RED_ZONE_SIZE);
+            let caller = record.map(|x| x.caller).unwrap_or(0);
+
+            if front_zone.iter().any(|x| *x != RED_ZONE_BYTE) {
+                panic!("Heap underflow before {:p} ({} bytes, allocated from {:#x})", ptr, layout.size(), caller);
+            }
+            if back_zone.iter().any(|x| *x != RED_ZONE_BYTE) {
+                panic!("Heap overflow after {:p} ({} bytes, allocated from {:#x})", ptr, layout.size(), caller);
+            }
+
+            header.magic = MAGIC_FREED;
+            core::ptr::write_bytes(block.add(size_of::<Header>()), POISON_BYTE, front - size_of::<Header>() + layout.size() + RED_ZONE_SIZE);
+            // Poisoned before the heap gets the block back, it unpoisons frames it returns to the frame allocator.
+            #[cfg(feature = "kasan")]
+            kasan::poison(block, front + layout.size() + RED_ZONE_SIZE, kasan::HEAP_FREED);
+        }
+        if let Some(record) = record {
+            self.freed[self.next_freed] = record;
+            self.next_freed = (self.next_freed + 1) % RECENTLY_FREED;
+        }
+        ptr.wrapping_sub(front)
+    }
+
+    fn track(&mut self, ptr: usize, size: usize, caller: usize) {
+        self.live += 1;
+        for i in 0..MAX_TRACKED {
+            let record = &mut self.records[(DebugHeap::slot(ptr) + i) % MAX_TRACKED];
+
+            if record.ptr == EMPTY || record.ptr == TOMBSTONE {
+                *record = Record { ptr, size, caller };
+                return;
+            }
+        }
+        self.untracked += 1;
+    }
+
+    fn untrack(&mut self, ptr: usize) -> Option<Record> {
+        for i in 0..MAX_TRACKED {
+            let record = &mut self.records[(DebugHeap::slot(ptr) + i) % MAX_TRACKED];
+
+            if record.ptr == EMPTY {
+                break;
+            }
+            if record.ptr == ptr {
+                let found = *record;
+
+                record.ptr = TOMBSTONE;
+                self.live -= 1;
+                return Some(found);
+            }
+        }
+        // Either an allocation that didn't fit in the table, or a bad free caught by the header check.
+        if self.untracked > 0 {
+            self.untracked -= 1;
+            self.live -= 1;
+        }
+        None
+    }
+
+    // Sizes include the red zones and headers.
+    #[inline]
+    pub fn allocated(&self) -> usize {
+        self.heap.allocated()
+    }
+
+    #[inline]
+    pub fn reserved(&self) -> usize {
+        self.heap.reserved()
+    }
+
+    // Log the live or recently freed allocation `addr` is in or next to (within a red zone).
+    pub fn describe(&self, addr: usize) {
+        let near = |record: &&Record| {
+            record.ptr != EMPTY
+                && record.ptr != TOMBSTONE
+                && addr + size_of::<Header>() + RED_ZONE_SIZE >= record.ptr
+                && addr < record.ptr + record.size + RED_ZONE_SIZE
+        };
+
+        if let Some(record) = self.records.iter().find(near) {
+            _log!(
+                "",
+                "        In or near allocation {:#x} ({} bytes), allocated from {:#x}",
+                record.ptr,
+                record.size,
+                record.caller
+            );
+        } else if let Some(record) = self.freed.iter().find(near) {
+            _log!(
+                "",
+                "        In or near freed allocation {:#x} ({} bytes), allocated from {:#x}",
+                record.ptr,
+                record.size,
+                record.caller
+            );
+        } else {
+            _log!("", "        Not near any tracked allocation");
+        }
+    }
+
+    // Log every live allocation, allocations that are never freed show up here.
+    pub fn dump(&self) {
+        debug!("Heap: {} live allocations ({} not tracked)", self.live, self.untracked);
+        for record in self.records.iter().filter(|x| x.ptr != EMPTY && x.ptr != TOMBSTONE) {
+            _log!(
+                "",
+                "        {:#x}: {} bytes, allocated from {:#x}",
+                record.ptr,
+                record.size,
+                record.caller
+            );
+        }
+    }
+}
@@ -1,11 +1,82 @@
-use core::{alloc::{GlobalAlloc, Layout}, ffi::c_void};
+use core::alloc::{GlobalAlloc, Layout};
 
-use crate::libs::generic::memory::allocators::liballoc::{free, malloc};
+extern crate alloc;
 
-struct Allocator {}
+use alloc::{boxed::Box, vec::Vec};
+use spin::Mutex;
 
+use crate::libs::generic::memory::allocators::heap::{self, Heap};
+#[cfg(feature = "heap-debug")]
+use crate::libs::generic::memory::allocators::debug::DebugHeap;
+
+#[cfg(not(feature = "heap-debug"))]
+type KernelHeap = Heap;
+#[cfg(feature = "heap-debug")]
+type KernelHeap = DebugHeap;
+
+struct Allocator {
+    heap: Mutex<KernelHeap>,
+}
+
+#[cfg(not(test))]
 #[global_allocator]
-static ALLOCATOR: Allocator = Allocator {};
+static ALLOCATOR: Allocator = Allocator {
+    heap: Mutex::new(KernelHeap::new()),
+};
+
+// Returned by the try_* functions when the heap can't serve an allocation.
+#[derive(Debug)]
+pub struct AllocError;
+
+// Bytes handed out by the heap and bytes it took from the frame allocator.
+#[cfg(not(test))]
+pub fn heap_usage() -> (usize, usize) {
+    let heap = ALLOCATOR.heap.lock();
+
+    (heap.allocated(), heap.reserved())
+}
+
+// Box::new, except running out of memory is reported instead of handled by the alloc error handler.
+pub fn try_box<T>(value: T) -> Result<Box<T>, AllocError> {
+    let layout = Layout::new::<T>();
+
+    if layout.size() == 0 {
+        return Ok(Box::new(value));
+    }
+
+    let ptr = unsafe { alloc::alloc::alloc(layout) } as *mut T;
+
+    if ptr.is_null() {
+        return Err(AllocError);
+    }
+    unsafe {
+        ptr.write(value);
+        Ok(Box::from_raw(ptr))
+    }
+}
+
+// Vec::with_capacity, except running out of memory is reported instead of handled by the alloc error handler.
+pub fn try_vec<T>(capacity: usize) -> Result<Vec<T>, AllocError> {
+    let mut vec = Vec::new();
+
+    vec.try_reserve_exact(capacity).map_err(|_| AllocError)?;
+    Ok(vec)
+}
+
+// Log the live heap allocations, to look for leaks.
+#[cfg(all(feature = "heap-debug", not(test)))]
+pub fn dump_allocations() {
+    ALLOCATOR.heap.lock().dump();
+}
+
+// Log the heap allocation around `addr`, skipped if the heap is busy (the report comes from inside it).
+#[cfg(all(feature = "kasan", not(test)))]
+pub fn describe_allocation(addr: usize) {
+    match ALLOCATOR.heap.try_lock() {
+        Some(heap) => heap.describe(addr),
+        None => crate::_log!("", "        Heap locked, allocation unknown"),
+    }
+}
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
@@ -21,6 +92,12 @@ unsafe impl GlobalAlloc for Allocator {
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
         let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+
+        // Same slab size class, the object already has room for the new size.
+        // The debug heap has a red zone right after the object, it always moves.
+        if cfg!(not(feature = "heap-debug")) && Heap::size_class(&layout).is_some() && Heap::size_class(&layout) == Heap::size_class(&new_layout) {
+            return ptr;
+        }
         let new_ptr = unsafe { self.alloc(new_layout) };
 
         if !new_ptr.is_null() {
@@ -32,11 +109,49 @@ unsafe impl GlobalAlloc for Allocator {
         new_ptr
     }
 
+    // Inlined into the allocator shim so that the return address is the code calling into alloc.
+    #[inline(always)]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        unsafe { malloc(layout.padding_needed_for(layout.align()) + layout.size()) as *mut u8 }
+        // Large allocations map a region of the kernel address space, which allocates from the heap.
+        cfg_select! {
+            feature = "heap-debug" => {
+                let caller = core::arch::return_address!() as usize;
+                let block_layout = DebugHeap::block_layout(&layout);
+
+                if Heap::size_class(&block_layout).is_none() {
+                    let block = heap::allocate_large(&block_layout);
+
+                    return if block.is_null() { block } else { unsafe { self.heap.lock().prepare(block, layout, caller) } };
+                }
+                self.heap.lock().allocate(layout, caller)
+            }
+            _ => {
+                if Heap::size_class(&layout).is_none() {
+                    return heap::allocate_large(&layout);
+                }
+                self.heap.lock().allocate(layout)
+            }
+        }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _: Layout) {
-        unsafe { free(ptr as *mut c_void); }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        cfg_select! {
+            feature = "heap-debug" => {
+                let block_layout = DebugHeap::block_layout(&layout);
+
+                if Heap::size_class(&block_layout).is_none() {
+                    let block = unsafe { self.heap.lock().release(ptr, layout) };
+
+                    return heap::free_large(block, &block_layout);
+                }
+                unsafe { self.heap.lock().free(ptr, layout) }
+            }
+            _ => {
+                if Heap::size_class(&layout).is_none() {
+                    return heap::free_large(ptr, &layout);
+                }
+                self.heap.lock().free(ptr, layout)
+            }
+        }
     }
 }
@@ -0,0 +1,277 @@
+use core::{
+    alloc::Layout,
+    ptr::null_mut,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::libs::{
+    arch::{self, x86_64::memory::paging::PageEntryFlags},
+    generic::memory::{
+        address::{PhysAddr, VirtAddr},
+        allocators::physical::pfa::PageFrameAllocator,
+        vm::Backing,
+        FRAME_ALLOCATOR, KERNEL_ADDRESS_SPACE,
+    },
+};
+#[cfg(feature = "kasan")]
+use crate::libs::generic::memory::kasan;
+
+// Objects are served from slabs of 2^N bytes, anything bigger than the last class
+// (or more aligned than a page) is given its own region of the kernel address space.
+const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+// Slabs hold at least this many objects so the header doesn't eat most of the slab.
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+// Bytes mapped for large allocations, they are served outside of the heap lock.
+static LARGE_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+// Header at the start of every slab, slabs are aligned on their size so an object's
+// slab can be found by masking its address.
+struct Slab {
+    next: *mut Slab,
+    prev: *mut Slab,
+    free: *mut FreeObject,
+    in_use: usize,
+}
+
+struct SizeClass {
+    object_size: usize,
+    // Slabs with at least one free object.
+    partial: *mut Slab,
+}
+
+pub struct Heap {
+    classes: [SizeClass; SIZE_CLASSES.len()],
+    // Bytes handed out, rounded up to their size class.
+    allocated: usize,
+    // Bytes taken from the frame allocator, slabs included.
+    reserved: usize,
+}
+
+// The heap only hands out pointers into the HHDM, it is safe to move between CPUs.
+unsafe impl Send for Heap {}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub const fn new() -> Self {
+        let mut classes = [const {
+            SizeClass {
+                object_size: 0,
+                partial: null_mut(),
+            }
+        }; SIZE_CLASSES.len()];
+        let mut i = 0;
+
+        while i < SIZE_CLASSES.len() {
+            classes[i].object_size = SIZE_CLASSES[i];
+            i += 1;
+        }
+        Self {
+            classes,
+            allocated: 0,
+            reserved: 0,
+        }
+    }
+
+    #[inline]
+    pub fn allocated(&self) -> usize {
+        self.allocated + LARGE_ALLOCATED.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn reserved(&self) -> usize {
+        self.reserved + LARGE_ALLOCATED.load(Ordering::Relaxed)
+    }
+
+    // Index of the size class able to hold this layout, None if it goes through allocate_large.
+    pub fn size_class(layout: &Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+
+        SIZE_CLASSES.iter().position(|x| *x >= size)
+    }
+
+    #[inline]
+    fn slab_size(object_size: usize) -> usize {
+        (object_size * MIN_OBJECTS_PER_SLAB).max(arch::paging::get_page_frame_size())
+    }
+
+    // Offset of the first object, past the header and aligned on the object size.
+    #[inline]
+    fn first_object_offset(object_size: usize) -> usize {
+        size_of::<Slab>().next_multiple_of(object_size)
+    }
+
+    // Allocations without a size class are served by allocate_large, null is returned here.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        match Heap::size_class(&layout) {
+            Some(class) => self.allocate_object(class),
+            None => null_mut(),
+        }
+    }
+
+    pub fn free(&mut self, ptr: *mut u8, layout: Layout) {
+        let class = Heap::size_class(&layout).expect("Large allocations are freed with free_large.");
+
+        self.free_object(class, ptr);
+    }
+
+    fn allocate_object(&mut self, class: usize) -> *mut u8 {
+        if self.classes[class].partial.is_null() && !self.grow(class) {
+            return null_mut();
+        }
+        unsafe {
+            let slab = self.classes[class].partial;
+            let object = (*slab).free;
+
+            (*slab).free = (*object).next;
+            (*slab).in_use += 1;
+            self.allocated += self.classes[class].object_size;
+            if (*slab).free.is_null() {
+                self.unlink(class, slab);
+            }
+            object as *mut u8
+        }
+    }
+
+    fn free_object(&mut self, class: usize, ptr: *mut u8) {
+        let object_size = self.classes[class].object_size;
+        let slab_size = Heap::slab_size(object_size);
+        let slab = ((ptr as usize) & !(slab_size - 1)) as *mut Slab;
+
+        unsafe {
+            let object = ptr as *mut FreeObject;
+
+            if (*slab).free.is_null() {
+                self.link(class, slab);
+            }
+            (*object).next = (*slab).free;
+            (*slab).free = object;
+            (*slab).in_use -= 1;
+            self.allocated -= object_size;
+
+            // Give empty slabs back, except the last one of the class so that
+            // a single allocation bouncing in and out doesn't hit the frame allocator every time.
+            if (*slab).in_use == 0 && !((*slab).next.is_null() && (*slab).prev.is_null()) {
+                self.unlink(class, slab);
+                #[cfg(feature = "kasan")]
+                kasan::unpoison(slab as *const u8, slab_size);
+                FRAME_ALLOCATOR.lock().free(
+                    PhysAddr::from_hhdm(VirtAddr::try_from(slab as u64).unwrap()),
+                    slab_size / arch::paging::get_page_frame_size(),
+                );
+                self.reserved -= slab_size;
+            }
+        }
+    }
+
+    // Allocate a new slab for a size class and thread its objects into a free list.
+    fn grow(&mut self, class: usize) -> bool {
+        let object_size = self.classes[class].object_size;
+        let slab_size = Heap::slab_size(object_size);
+        let Ok(slab_addr) = FRAME_ALLOCATOR.lock().allocate_contiguous_range(slab_size, false) else {
+            return false;
+        };
+        let base: *mut u8 = slab_addr.as_hhdm().into();
+        let slab = base as *mut Slab;
+
+        self.reserved += slab_size;
+
+        unsafe {
+            let mut free: *mut FreeObject = null_mut();
+
+            // Build the list backwards so objects are handed out in address order.
+            for offset in (Heap::first_object_offset(object_size)..slab_size)
+                .step_by(object_size)
+                .rev()
+            {
+                let object = base.add(offset) as *mut FreeObject;
+
+                (*object).next = free;
+                free = object;
+            }
+            slab.write(Slab {
+                next: null_mut(),
+                prev: null_mut(),
+                free,
+                in_use: 0,
+            });
+        }
+        self.link(class, slab);
+        true
+    }
+
+    fn link(&mut self, class: usize, slab: *mut Slab) {
+        let head = self.classes[class].partial;
+
+        unsafe {
+            (*slab).prev = null_mut();
+            (*slab).next = head;
+            if !head.is_null() {
+                (*head).prev = slab;
+            }
+        }
+        self.classes[class].partial = slab;
+    }
+
+    fn unlink(&mut self, class: usize, slab: *mut Slab) {
+        unsafe {
+            if (*slab).prev.is_null() {
+                self.classes[class].partial = (*slab).next;
+            } else {
+                (*(*slab).prev).next = (*slab).next;
+            }
+            if !(*slab).next.is_null() {
+                (*(*slab).next).prev = (*slab).prev;
+            }
+            (*slab).next = null_mut();
+            (*slab).prev = null_mut();
+        }
+    }
+}
+
+// Map an allocation too large for slabs in its own region of the kernel address space, it only
+// needs free frames and not a contiguous block. The region tree is allocated from the heap,
+// the caller must not hold the heap lock.
+pub fn allocate_large(layout: &Layout) -> *mut u8 {
+    let mut space = KERNEL_ADDRESS_SPACE.lock();
+    let Some(space) = space.as_mut() else {
+        return null_mut();
+    };
+
+    match space.allocate(
+        layout.size(),
+        layout.align(),
+        PageEntryFlags::Present | PageEntryFlags::ReadWrite | PageEntryFlags::ExecuteDisabled,
+        Backing::Anonymous,
+    ) {
+        Ok(addr) => {
+            LARGE_ALLOCATED.fetch_add(layout.size().next_multiple_of(arch::paging::get_page_frame_size()), Ordering::Relaxed);
+            addr.into()
+        }
+        // Out of memory is reported to the caller, GlobalAlloc users get the alloc error handler.
+        Err(_) => null_mut(),
+    }
+}
+
+// Unmap an allocation made by allocate_large, the caller must not hold the heap lock.
+pub fn free_large(ptr: *mut u8, layout: &Layout) {
+    let region = KERNEL_ADDRESS_SPACE
+        .lock()
+        .as_mut()
+        .expect("Large heap allocations can't be freed before memory::init.")
+        .unmap(VirtAddr::try_from(ptr as u64).unwrap())
+        .expect("Freeing a large heap allocation that isn't mapped.");
+
+    debug_assert!(region.length == layout.size().next_multiple_of(arch::paging::get_page_frame_size()));
+    LARGE_ALLOCATED.fetch_sub(region.length, Ordering::Relaxed);
+}
@@ -0,0 +1,305 @@
+use limine::memory_map::{Entry, EntryType};
+
+use crate::libs::generic::memory::{
+    address::PhysAddr,
+    allocators::physical::pfa::{FrameAllocatorError, PageFrameAllocator},
+};
+
+// Frames below this address are never handed out (IVT, BDA, real-mode trampolines).
+const LOW_MEMORY_LIMIT: u64 = 1 << 16;
+
+// Fixed-size bit array living in memory we don't own (usually a HHDM pointer).
+pub struct Bitmap {
+    words: *mut u64,
+    bits: usize,
+}
+
+// The storage is only reachable through the bitmap, so it can move along with it.
+unsafe impl Send for Bitmap {}
+
+impl Bitmap {
+    pub const fn empty() -> Self {
+        Self {
+            words: core::ptr::null_mut(),
+            bits: 0,
+        }
+    }
+
+    #[inline]
+    pub fn storage_size(bits: usize) -> usize {
+        bits.div_ceil(64) * size_of::<u64>()
+    }
+
+    // `storage` must point to at least `Bitmap::storage_size(bits)` bytes that outlive the bitmap.
+    pub unsafe fn new(storage: *mut u64, bits: usize, value: bool) -> Self {
+        unsafe {
+            core::ptr::write_bytes(storage, if value { 0xFF } else { 0x00 }, bits.div_ceil(64));
+        }
+        Self {
+            words: storage,
+            bits,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bits
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    #[inline]
+    pub fn get(&self, index: usize) -> bool {
+        assert!(index < self.bits, "Bitmap index {} out of bounds ({})", index, self.bits);
+        unsafe { *self.words.add(index / 64) & (1 << (index % 64)) != 0 }
+    }
+
+    #[inline]
+    pub fn set(&mut self, index: usize, value: bool) {
+        assert!(index < self.bits, "Bitmap index {} out of bounds ({})", index, self.bits);
+        let word: &mut u64 = unsafe { &mut *self.words.add(index / 64) };
+
+        if value {
+            *word |= 1 << (index % 64);
+        } else {
+            *word &= !(1 << (index % 64));
+        }
+    }
+
+    pub fn set_range(&mut self, start: usize, count: usize, value: bool) {
+        for index in start..start + count {
+            self.set(index, value);
+        }
+    }
+
+    // Look for `count` consecutive cleared bits, starting at `start` and wrapping around once.
+    pub fn find_clear_run(&self, start: usize, count: usize) -> Option<usize> {
+        if count == 0 || count > self.bits {
+            return None;
+        }
+        let start = if start >= self.bits { 0 } else { start };
+
+        self.find_clear_run_in(start, self.bits, count)
+            .or_else(|| self.find_clear_run_in(0, (start + count - 1).min(self.bits), count))
+    }
+
+    fn find_clear_run_in(&self, from: usize, to: usize, count: usize) -> Option<usize> {
+        let mut run_start = from;
+        let mut run = 0;
+        let mut index = from;
+
+        while index < to {
+            // Skip whole words at once when they are entirely set, this is the common case
+            // when the low end of memory is already handed out.
+            if run == 0 && index % 64 == 0 && unsafe { *self.words.add(index / 64) } == u64::MAX {
+                index += 64;
+                run_start = index;
+                continue;
+            }
+            if self.get(index) {
+                run = 0;
+                run_start = index + 1;
+            } else {
+                run += 1;
+                if run == count {
+                    return Some(run_start);
+                }
+            }
+            index += 1;
+        }
+        None
+    }
+}
+
+// One bit per page frame (set = in use), indexed by physical frame number.
+// The bitmap itself is stored at the start of the first usable region large enough to hold it.
+pub struct BitmapAllocator {
+    bitmap: Bitmap,
+    pfsize: usize,
+    total: usize,
+    used: usize,
+    next: usize,
+}
+
+impl BitmapAllocator {
+    fn is_usable(entry: &Entry, pfsize: usize) -> bool {
+        entry.entry_type == EntryType::USABLE
+            && entry.length >= pfsize as u64
+            && entry.base > LOW_MEMORY_LIMIT
+    }
+
+    // Memory we can only use once the bootloader structures and ACPI tables aren't needed anymore.
+    pub fn is_reclaimable(entry: &Entry, pfsize: usize) -> bool {
+        (entry.entry_type == EntryType::BOOTLOADER_RECLAIMABLE || entry.entry_type == EntryType::ACPI_RECLAIMABLE)
+            && entry.length >= pfsize as u64
+            && entry.base > LOW_MEMORY_LIMIT
+    }
+
+    pub fn new(memory_map: &[&Entry], pfsize: usize) -> Self {
+        let usable = || memory_map.iter().filter(|x| BitmapAllocator::is_usable(x, pfsize));
+        // Reclaimable memory is left out for now, but the bitmap already covers it
+        // so that allocators built on top of it can take it over later.
+        let frames = memory_map
+            .iter()
+            .filter(|x| BitmapAllocator::is_usable(x, pfsize) || BitmapAllocator::is_reclaimable(x, pfsize))
+            .map(|x| ((x.base + x.length) / pfsize as u64) as usize)
+            .max()
+            .expect("No usable memory found in the memory map.");
+        let storage_size = Bitmap::storage_size(frames);
+        let storage = usable()
+            .find(|x| x.length >= storage_size as u64)
+            .expect("No usable memory region is large enough to hold the page frame bitmap.");
+        let mut bitmap = unsafe {
+            Bitmap::new(
+                PhysAddr::from(storage.base).as_hhdm().as_mut_ptr::<u64>(),
+                frames,
+                true,
+            )
+        };
+        let mut total = 0;
+
+        for entry in usable() {
+            let first = entry.base.div_ceil(pfsize as u64) as usize;
+            let last = ((entry.base + entry.length) / pfsize as u64) as usize;
+
+            bitmap.set_range(first, last - first, false);
+            total += last - first;
+        }
+
+        let storage_frames = storage_size.div_ceil(pfsize);
+
+        bitmap.set_range(storage.base as usize / pfsize, storage_frames, true);
+        Self {
+            bitmap,
+            pfsize,
+            total,
+            used: storage_frames,
+            next: 0,
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.bitmap.bits
+    }
+
+    // First run of free frames at or after `start`, as [first, last).
+    fn next_free_run(&self, start: usize) -> Option<(usize, usize)> {
+        let first = self.bitmap.find_clear_run_in(start, self.bitmap.bits, 1)?;
+        let mut last = first;
+
+        while last < self.bitmap.bits && !self.bitmap.get(last) {
+            last += 1;
+        }
+        Some((first, last))
+    }
+
+    // Hand every frame that is still free over to `f` as contiguous runs, leaving them free.
+    pub fn free_runs(&self, mut f: impl FnMut(PhysAddr, usize)) {
+        let mut start = 0;
+
+        while let Some((first, last)) = self.next_free_run(start) {
+            f(PhysAddr::from((first * self.pfsize) as u64), last - first);
+            start = last;
+        }
+    }
+
+    // Hand every frame that is still free over to `f` as contiguous runs and mark them used.
+    pub fn drain(&mut self, mut f: impl FnMut(PhysAddr, usize)) {
+        let mut start = 0;
+
+        while let Some((first, last)) = self.next_free_run(start) {
+            self.bitmap.set_range(first, last - first, true);
+            self.used += last - first;
+            f(PhysAddr::from((first * self.pfsize) as u64), last - first);
+            start = last;
+        }
+    }
+
+    fn allocate_frames(&mut self, count: usize, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        let first = self
+            .bitmap
+            .find_clear_run(self.next, count)
+            .ok_or(FrameAllocatorError::OutOfMemory)?;
+        let head = PhysAddr::from((first * self.pfsize) as u64);
+
+        self.bitmap.set_range(first, count, true);
+        self.used += count;
+        self.next = first + count;
+        if clear {
+            unsafe {
+                core::ptr::write_bytes(Into::<*mut u8>::into(head.as_hhdm()), 0, count * self.pfsize);
+            }
+        }
+        Ok(head)
+    }
+}
+
+impl PageFrameAllocator for BitmapAllocator {
+    fn allocate(&mut self, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_frames(1, clear)
+    }
+
+    fn allocate_contiguous_range(&mut self, size: usize, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_frames(size.max(1).div_ceil(self.pfsize), clear)
+    }
+
+    fn free(&mut self, addr: PhysAddr, count: usize) {
+        let first = (Into::<u64>::into(addr) / self.pfsize as u64) as usize;
+
+        for frame in first..first + count {
+            assert!(
+                self.bitmap.get(frame),
+                "Double free of page frame 0x{:02x}",
+                frame * self.pfsize
+            );
+            self.bitmap.set(frame, false);
+        }
+        self.used -= count;
+        // Prefer reusing low frames, this keeps the bitmap dense at the start.
+        self.next = self.next.min(first);
+    }
+
+    fn available_total(&self) -> usize {
+        self.total * self.pfsize
+    }
+
+    fn used(&self) -> usize {
+        self.used * self.pfsize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec;
+    use crate::libs::generic::memory::allocators::physical::bitmap::Bitmap;
+
+    #[test]
+    fn bitmap_find_clear_run() {
+        let mut storage = vec![0u64; 4];
+        let mut bitmap = unsafe { Bitmap::new(storage.as_mut_ptr(), 200, true) };
+
+        assert_eq!(bitmap.find_clear_run(0, 1), None);
+        bitmap.set_range(70, 10, false);
+        assert_eq!(bitmap.find_clear_run(0, 10), Some(70));
+        assert_eq!(bitmap.find_clear_run(0, 11), None);
+        bitmap.set(72, true);
+        assert_eq!(bitmap.find_clear_run(0, 3), Some(73));
+        assert_eq!(bitmap.find_clear_run(0, 2), Some(70));
+    }
+
+    #[test]
+    fn bitmap_find_clear_run_wraps_around() {
+        let mut storage = vec![0u64; 2];
+        let mut bitmap = unsafe { Bitmap::new(storage.as_mut_ptr(), 128, true) };
+
+        bitmap.set_range(4, 4, false);
+        assert_eq!(bitmap.find_clear_run(100, 4), Some(4));
+        assert_eq!(bitmap.find_clear_run(6, 4), Some(4));
+        assert_eq!(bitmap.find_clear_run(128, 2), Some(4));
+    }
+}
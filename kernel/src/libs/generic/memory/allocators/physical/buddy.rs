@@ -0,0 +1,377 @@
+use crate::libs::generic::memory::{
+    address::PhysAddr,
+    allocators::physical::{
+        bitmap::{Bitmap, BitmapAllocator},
+        pfa::{FrameAllocatorError, PageFrameAllocator},
+        zone::{Zone, ZONE_COUNT},
+    },
+};
+
+// Blocks go from 1 frame (order 0) up to 2^(MAX_ORDER - 1) frames (4 MiB with 4 KiB frames).
+pub const MAX_ORDER: usize = 11;
+
+// Physical address 0 is never handed out, so we use it as the end of list marker.
+const NO_BLOCK: u64 = 0;
+
+// Free list node, written at the start of every free block (through the HHDM).
+struct FreeBlock {
+    next: u64,
+    prev: u64,
+}
+
+// Binary buddy allocator, blocks of order N are 2^N frames long and aligned on their size.
+// It manages a span of physical memory, frames are handed over with add_range.
+// Every zone has its own free lists, allocations go through the zones in fallback order.
+pub struct BuddyAllocator {
+    free_lists: [[u64; MAX_ORDER]; ZONE_COUNT],
+    // One bit per block of each order, set when the block is the head of a free block of that order.
+    free_maps: [Bitmap; MAX_ORDER],
+    // Number of mappings of each allocated frame, frames shared copy-on-write have more than one.
+    references: *mut u16,
+    // First frame of the span, aligned on the largest block.
+    first: usize,
+    frames: usize,
+    pfsize: usize,
+    total: usize,
+    // Free and managed frames of each zone.
+    zone_free: [usize; ZONE_COUNT],
+    zone_total: [usize; ZONE_COUNT],
+}
+
+// The reference counts are only reachable through the allocator.
+unsafe impl Send for BuddyAllocator {}
+
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuddyAllocator {
+    pub const fn new() -> Self {
+        Self {
+            free_lists: [[NO_BLOCK; MAX_ORDER]; ZONE_COUNT],
+            free_maps: [const { Bitmap::empty() }; MAX_ORDER],
+            references: core::ptr::null_mut(),
+            first: 0,
+            frames: 0,
+            pfsize: 0,
+            total: 0,
+            zone_free: [0; ZONE_COUNT],
+            zone_total: [0; ZONE_COUNT],
+        }
+    }
+
+    // Set up the allocator for [start, end), with its metadata taken from `source`.
+    pub fn init(&mut self, source: &mut BitmapAllocator, pfsize: usize, start: PhysAddr, end: PhysAddr) {
+        let first = (Into::<u64>::into(start) / pfsize as u64) as usize & !((1 << (MAX_ORDER - 1)) - 1);
+        let frames = Into::<u64>::into(end).div_ceil(pfsize as u64) as usize - first;
+        let maps_size: usize = (0..MAX_ORDER)
+            .map(|order| Bitmap::storage_size((frames >> order) + 1))
+            .sum();
+        let storage_size = maps_size + frames * size_of::<u16>();
+        let storage = source
+            .allocate_contiguous_range(storage_size, true)
+            .expect("Not enough memory to hold the buddy allocator free maps.");
+        let mut words: *mut u64 = unsafe { storage.as_hhdm().as_mut_ptr::<u64>() };
+
+        self.pfsize = pfsize;
+        for (order, free_map) in self.free_maps.iter_mut().enumerate() {
+            let bits = (frames >> order) + 1;
+
+            unsafe {
+                *free_map = Bitmap::new(words, bits, false);
+                words = words.add(Bitmap::storage_size(bits) / size_of::<u64>());
+            }
+        }
+        self.references = words as *mut u16;
+        self.first = first;
+        self.frames = frames;
+    }
+
+    // Hand free frames over to the allocator.
+    pub fn add_range(&mut self, addr: PhysAddr, count: usize) {
+        let first = self.frame_number(addr);
+
+        assert!(self.manages(addr) && first + count <= self.first + self.frames, "Page frames 0x{:02x} are not managed by the allocator", addr);
+        let before = self.zone_free;
+
+        self.free_range(first, count);
+        self.total += count;
+        for zone in Zone::ALL {
+            self.zone_total[zone as usize] += self.zone_free[zone as usize] - before[zone as usize];
+        }
+    }
+
+    // Free memory in a zone, in bytes.
+    #[inline]
+    pub fn zone_free(&self, zone: Zone) -> usize {
+        self.zone_free[zone as usize] * self.pfsize
+    }
+
+    // Memory managed in a zone, in bytes.
+    #[inline]
+    pub fn zone_total(&self, zone: Zone) -> usize {
+        self.zone_total[zone as usize] * self.pfsize
+    }
+
+    #[inline]
+    pub fn manages(&self, addr: PhysAddr) -> bool {
+        (self.first..self.first + self.frames).contains(&self.frame_number(addr))
+    }
+
+    #[inline]
+    fn frame_number(&self, addr: PhysAddr) -> usize {
+        (Into::<u64>::into(addr) / self.pfsize as u64) as usize
+    }
+
+    #[inline]
+    fn zone_of(&self, frame: usize) -> Zone {
+        Zone::of(PhysAddr::from((frame * self.pfsize) as u64))
+    }
+
+    #[inline]
+    fn block(&self, frame: usize) -> *mut FreeBlock {
+        unsafe {
+            PhysAddr::from((frame * self.pfsize) as u64)
+                .as_hhdm()
+                .as_mut_ptr::<FreeBlock>()
+        }
+    }
+
+    #[inline]
+    fn references_of(&mut self, frame: usize) -> &mut u16 {
+        assert!(
+            (self.first..self.first + self.frames).contains(&frame),
+            "Page frame 0x{:02x} is not managed by the allocator",
+            frame * self.pfsize
+        );
+        unsafe { &mut *self.references.add(frame - self.first) }
+    }
+
+    // Smallest order whose blocks can hold `count` frames.
+    #[inline]
+    pub fn order_for(count: usize) -> usize {
+        count.max(1).next_power_of_two().trailing_zeros() as usize
+    }
+
+    fn push(&mut self, frame: usize, order: usize) {
+        let zone = self.zone_of(frame) as usize;
+        let head = self.free_lists[zone][order];
+        let block = self.block(frame);
+
+        unsafe {
+            (*block).next = head;
+            (*block).prev = NO_BLOCK;
+            if head != NO_BLOCK {
+                (*self.block(self.frame_number(head.into()))).prev = (frame * self.pfsize) as u64;
+            }
+        }
+        self.free_lists[zone][order] = (frame * self.pfsize) as u64;
+        self.free_maps[order].set((frame - self.first) >> order, true);
+        self.zone_free[zone] += 1 << order;
+    }
+
+    fn remove(&mut self, frame: usize, order: usize) {
+        let zone = self.zone_of(frame) as usize;
+        let block = self.block(frame);
+        let (next, prev) = unsafe { ((*block).next, (*block).prev) };
+
+        unsafe {
+            if prev == NO_BLOCK {
+                self.free_lists[zone][order] = next;
+            } else {
+                (*self.block(self.frame_number(prev.into()))).next = next;
+            }
+            if next != NO_BLOCK {
+                (*self.block(self.frame_number(next.into()))).prev = prev;
+            }
+        }
+        self.free_maps[order].set((frame - self.first) >> order, false);
+        self.zone_free[zone] -= 1 << order;
+    }
+
+    fn is_free(&self, frame: usize, order: usize) -> bool {
+        let index = (frame - self.first) >> order;
+
+        index < self.free_maps[order].len() && self.free_maps[order].get(index)
+    }
+
+    // Give a block back, merging it with its buddy for as long as the buddy is free too.
+    fn insert(&mut self, mut frame: usize, mut order: usize) {
+        for containing_order in 0..MAX_ORDER {
+            assert!(
+                !self.is_free(frame & !((1 << containing_order) - 1), containing_order),
+                "Double free of page frame 0x{:02x}",
+                frame * self.pfsize
+            );
+        }
+        while order < MAX_ORDER - 1 {
+            let buddy = frame ^ (1 << order);
+
+            if !self.is_free(buddy, order) {
+                break;
+            }
+            self.remove(buddy, order);
+            frame = frame.min(buddy);
+            order += 1;
+        }
+        self.push(frame, order);
+    }
+
+    // Split an arbitrary run of frames into naturally aligned blocks and free each of them.
+    fn free_range(&mut self, mut frame: usize, mut count: usize) {
+        while count > 0 {
+            let order = (frame.trailing_zeros() as usize)
+                .min(count.ilog2() as usize)
+                .min(MAX_ORDER - 1);
+
+            self.insert(frame, order);
+            frame += 1 << order;
+            count -= 1 << order;
+        }
+    }
+
+    // First free block of `order` in `zone` whose first `count` frames end before `limit` (a frame number).
+    fn find_below(&self, zone: Zone, order: usize, count: usize, limit: usize) -> Option<usize> {
+        let mut block = self.free_lists[zone as usize][order];
+
+        while block != NO_BLOCK {
+            let frame = self.frame_number(block.into());
+
+            if frame + count <= limit {
+                return Some(frame);
+            }
+            block = unsafe { (*self.block(frame)).next };
+        }
+        None
+    }
+
+    // Look in `zone` first, then in its fallbacks, for a block of at least `order` ending before `limit`.
+    fn find_block(&self, zone: Zone, order: usize, count: usize, limit: usize) -> Option<(usize, usize)> {
+        let mut zone = Some(zone);
+
+        while let Some(current) = zone {
+            let found = (order..MAX_ORDER).find_map(|x| self.find_below(current, x, count, limit).map(|frame| (x, frame)));
+
+            if found.is_some() {
+                return found;
+            }
+            zone = current.fallback();
+        }
+        None
+    }
+
+    // Blocks are aligned on their size, so alignment is obtained by asking for a large enough order.
+    fn allocate_frames(&mut self, zone: Zone, count: usize, alignment: usize, limit: usize, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        let order = BuddyAllocator::order_for(count).max(BuddyAllocator::order_for(alignment));
+
+        if order >= MAX_ORDER {
+            return Err(FrameAllocatorError::RangeTooLarge);
+        }
+
+        let (mut current, frame) = self
+            .find_block(zone, order, count, limit)
+            .ok_or(FrameAllocatorError::OutOfMemory)?;
+
+        self.remove(frame, current);
+        // Split the block until it has the requested order, freeing the upper halves.
+        while current > order {
+            current -= 1;
+            self.push(frame + (1 << current), current);
+        }
+        // Only keep the frames that were asked for, the tail goes back to the free lists.
+        if count < 1 << order {
+            self.free_range(frame + count, (1 << order) - count);
+        }
+        for allocated in frame..frame + count {
+            *self.references_of(allocated) = 1;
+        }
+
+        let head = PhysAddr::from((frame * self.pfsize) as u64);
+
+        if clear {
+            unsafe {
+                core::ptr::write_bytes(Into::<*mut u8>::into(head.as_hhdm()), 0, count * self.pfsize);
+            }
+        }
+        Ok(head)
+    }
+
+    // Contiguous frames aligned on `alignment` bytes and ending below `limit`, for devices
+    // that can't address the whole physical memory.
+    pub fn allocate_below(&mut self, size: usize, alignment: usize, limit: PhysAddr, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_frames(
+            Zone::highest_below(limit),
+            size.max(1).div_ceil(self.pfsize),
+            alignment.div_ceil(self.pfsize),
+            self.frame_number(limit),
+            clear,
+        )
+    }
+
+    // Contiguous frames aligned on `alignment` bytes from `zone`, or from its fallbacks if it is exhausted.
+    pub fn allocate_in(&mut self, zone: Zone, size: usize, alignment: usize, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_frames(
+            zone,
+            size.max(1).div_ceil(self.pfsize),
+            alignment.div_ceil(self.pfsize),
+            usize::MAX,
+            clear,
+        )
+    }
+
+    // Add a mapping to an allocated frame, it is only freed once every mapping released it.
+    pub fn reference(&mut self, addr: PhysAddr) {
+        let references = self.references_of(self.frame_number(addr));
+
+        assert!(*references > 0, "Referencing free page frame 0x{:02x}", addr);
+        *references = references.checked_add(1).expect("Too many references to a page frame.");
+    }
+
+    pub fn references(&mut self, addr: PhysAddr) -> usize {
+        *self.references_of(self.frame_number(addr)) as usize
+    }
+
+    // Drop a mapping of a frame, freeing it if it was the last one.
+    pub fn release(&mut self, addr: PhysAddr) {
+        let frame = self.frame_number(addr);
+        let references = self.references_of(frame);
+
+        assert!(*references > 0, "Releasing free page frame 0x{:02x}", addr);
+        *references -= 1;
+        if *references == 0 {
+            self.free_range(frame, 1);
+        }
+    }
+}
+
+impl PageFrameAllocator for BuddyAllocator {
+    fn allocate(&mut self, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_frames(Zone::Normal, 1, 1, usize::MAX, clear)
+    }
+
+    fn allocate_contiguous_range(&mut self, size: usize, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_frames(Zone::Normal, size.max(1).div_ceil(self.pfsize), 1, usize::MAX, clear)
+    }
+
+    fn free(&mut self, addr: PhysAddr, count: usize) {
+        let first = self.frame_number(addr);
+
+        for frame in first..first + count {
+            let references = self.references_of(frame);
+
+            assert!(*references <= 1, "Freeing shared page frame 0x{:02x}", frame * self.pfsize);
+            *references = 0;
+        }
+        self.free_range(first, count);
+    }
+
+    fn available_total(&self) -> usize {
+        self.total * self.pfsize
+    }
+
+    fn used(&self) -> usize {
+        (self.total - self.zone_free.iter().sum::<usize>()) * self.pfsize
+    }
+}
@@ -0,0 +1,206 @@
+use crate::libs::{
+    arch,
+    generic::memory::{
+        address::PhysAddr,
+        allocators::physical::{
+            bitmap::BitmapAllocator,
+            buddy::BuddyAllocator,
+            pfa::{FrameAllocatorError, PageFrameAllocator},
+            zone::Zone,
+        },
+        numa::{NumaTopology, MAX_NODES},
+    },
+};
+
+// One buddy allocator per NUMA node, allocations are served by the node of the running CPU
+// and fall back to the other nodes, nearest first.
+pub struct NumaAllocator {
+    topology: NumaTopology,
+    nodes: [BuddyAllocator; MAX_NODES],
+    pfsize: usize,
+    total: usize,
+}
+
+impl Default for NumaAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NumaAllocator {
+    pub const fn new() -> Self {
+        Self {
+            topology: NumaTopology::uniform(),
+            nodes: [const { BuddyAllocator::new() }; MAX_NODES],
+            pfsize: 0,
+            total: 0,
+        }
+    }
+
+    // Take over every frame `source` has left, each going to the node that owns it.
+    pub fn init(&mut self, source: &mut BitmapAllocator, pfsize: usize, topology: NumaTopology) {
+        let end = (source.frames() * pfsize) as u64;
+
+        self.topology = topology;
+        self.pfsize = pfsize;
+        arch::set_current_numa_node(self.topology.node_of_cpu(arch::current_cpu_id()));
+
+        let mut spans = [None; MAX_NODES];
+
+        for (node, span) in spans.iter_mut().enumerate().take(self.topology.node_count()) {
+            *span = self.topology.span(node, end);
+        }
+        // Free memory the SRAT doesn't describe goes to node 0, its allocator has to cover it as well.
+        source.free_runs(|addr, count| {
+            let start: u64 = addr.into();
+
+            NumaAllocator::node_runs(&self.topology, pfsize, start, start + (count * pfsize) as u64, |node, start, end| {
+                if node == 0 {
+                    let (first, last) = spans[0].unwrap_or((start, end));
+
+                    spans[0] = Some((first.min(start), last.max(end)));
+                }
+            });
+        });
+        // The metadata of every node comes from wherever the bitmap finds room, not from the node itself.
+        for (node, span) in spans.iter().enumerate() {
+            if let Some((start, node_end)) = span {
+                self.nodes[node].init(source, pfsize, PhysAddr::from(*start), PhysAddr::from(*node_end));
+            }
+        }
+        self.total = source.available_total() / pfsize;
+        source.drain(|addr, count| self.distribute(addr, count));
+    }
+
+    // Split [start, end) into runs of frames owned by a single node.
+    fn node_runs(topology: &NumaTopology, pfsize: usize, mut start: u64, end: u64, mut f: impl FnMut(usize, u64, u64)) {
+        while start < end {
+            let (node, node_end) = topology.node_extent(start);
+            let run_end = node_end.next_multiple_of(pfsize as u64).min(end);
+
+            f(node, start, run_end);
+            start = run_end;
+        }
+    }
+
+    // Hand a run of frames to the nodes owning them.
+    fn distribute(&mut self, addr: PhysAddr, count: usize) {
+        let start: u64 = addr.into();
+        let pfsize = self.pfsize;
+
+        NumaAllocator::node_runs(&self.topology, pfsize, start, start + (count * pfsize) as u64, |node, start, end| {
+            self.nodes[node].add_range(PhysAddr::from(start), ((end - start) / pfsize as u64) as usize);
+        });
+    }
+
+    // Hand frames that weren't usable at init time over to the allocator.
+    pub fn add_range(&mut self, addr: PhysAddr, count: usize) {
+        self.distribute(addr, count);
+        self.total += count;
+    }
+
+    #[inline]
+    pub fn topology(&self) -> &NumaTopology {
+        &self.topology
+    }
+
+    #[inline]
+    pub fn node(&self, node: usize) -> &BuddyAllocator {
+        &self.nodes[node]
+    }
+
+    // Node of the CPU running the allocation.
+    #[inline]
+    pub fn local_node(&self) -> usize {
+        arch::current_numa_node()
+    }
+
+    #[inline]
+    fn node_of(&self, addr: PhysAddr) -> usize {
+        self.topology.node_of_address(addr.into())
+    }
+
+    // Try `allocate` on `preferred` first, then on the other nodes nearest first.
+    fn allocate_from(
+        &mut self,
+        preferred: usize,
+        mut allocate: impl FnMut(&mut BuddyAllocator) -> Result<PhysAddr, FrameAllocatorError>,
+    ) -> Result<PhysAddr, FrameAllocatorError> {
+        let mut order = [0; MAX_NODES];
+        let count = self.topology.nodes_by_distance(preferred, &mut order);
+
+        for node in order[..count].iter() {
+            match allocate(&mut self.nodes[*node]) {
+                Err(FrameAllocatorError::OutOfMemory) => continue,
+                result => return result,
+            }
+        }
+        Err(FrameAllocatorError::OutOfMemory)
+    }
+
+    // Contiguous frames aligned on `alignment` bytes and ending below `limit`, local node first.
+    pub fn allocate_below(&mut self, size: usize, alignment: usize, limit: PhysAddr, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_from(self.local_node(), |node| node.allocate_below(size, alignment, limit, clear))
+    }
+
+    // Contiguous frames from `zone` or its fallbacks, local node first.
+    pub fn allocate_in(&mut self, zone: Zone, size: usize, alignment: usize, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_from(self.local_node(), |node| node.allocate_in(zone, size, alignment, clear))
+    }
+
+    // Contiguous frames from `node`, or from the nearest node with free memory.
+    pub fn allocate_on(&mut self, node: usize, size: usize, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_from(node, |node| node.allocate_contiguous_range(size, clear))
+    }
+
+    pub fn zone_free(&self, zone: Zone) -> usize {
+        self.nodes.iter().map(|x| x.zone_free(zone)).sum()
+    }
+
+    pub fn zone_total(&self, zone: Zone) -> usize {
+        self.nodes.iter().map(|x| x.zone_total(zone)).sum()
+    }
+
+    pub fn reference(&mut self, addr: PhysAddr) {
+        let node = self.node_of(addr);
+
+        self.nodes[node].reference(addr);
+    }
+
+    pub fn references(&mut self, addr: PhysAddr) -> usize {
+        let node = self.node_of(addr);
+
+        self.nodes[node].references(addr)
+    }
+
+    pub fn release(&mut self, addr: PhysAddr) {
+        let node = self.node_of(addr);
+
+        self.nodes[node].release(addr);
+    }
+}
+
+impl PageFrameAllocator for NumaAllocator {
+    fn allocate(&mut self, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_from(self.local_node(), |node| node.allocate(clear))
+    }
+
+    fn allocate_contiguous_range(&mut self, size: usize, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_from(self.local_node(), |node| node.allocate_contiguous_range(size, clear))
+    }
+
+    // Contiguous ranges never span two nodes, they come from a single buddy allocator.
+    fn free(&mut self, addr: PhysAddr, count: usize) {
+        let node = self.node_of(addr);
+
+        self.nodes[node].free(addr, count);
+    }
+
+    fn available_total(&self) -> usize {
+        self.total * self.pfsize
+    }
+
+    fn used(&self) -> usize {
+        self.available_total() - self.nodes.iter().map(|x| x.available_total() - x.used()).sum::<usize>()
+    }
+}
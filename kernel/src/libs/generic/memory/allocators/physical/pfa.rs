@@ -1,9 +1,16 @@
 use crate::libs::generic::memory::address::PhysAddr;
 
+#[derive(Debug)]
+pub enum FrameAllocatorError {
+    OutOfMemory,
+    // The allocator cannot serve that many contiguous frames at once.
+    RangeTooLarge,
+}
+
 pub trait PageFrameAllocator {
-    fn allocate(clear: bool) -> PhysAddr;
-    fn allocate_contiguous_range(size: usize, clear: bool) -> PhysAddr;
-    fn free();
-    fn available_total() -> usize;
-    fn used() -> usize;
+    fn allocate(&mut self, clear: bool) -> Result<PhysAddr, FrameAllocatorError>;
+    fn allocate_contiguous_range(&mut self, size: usize, clear: bool) -> Result<PhysAddr, FrameAllocatorError>;
+    fn free(&mut self, addr: PhysAddr, count: usize);
+    fn available_total(&self) -> usize;
+    fn used(&self) -> usize;
 }
@@ -0,0 +1,92 @@
+use crate::libs::generic::memory::address::PhysAddr;
+
+pub const ZONE_COUNT: usize = 3;
+
+// Physical memory split by the addresses devices can reach.
+// Zone boundaries are aligned on the largest buddy block, so a block never spans two zones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    // Below 16 MiB, for ISA DMA and legacy ATA controllers.
+    Dma = 0,
+    // Below 4 GiB, for devices with 32 bit addressing.
+    Dma32 = 1,
+    Normal = 2,
+}
+
+impl Zone {
+    pub const ALL: [Zone; ZONE_COUNT] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+
+    #[inline]
+    pub fn start(self) -> u64 {
+        match self {
+            Zone::Dma => 0,
+            Zone::Dma32 => 1 << 24,
+            Zone::Normal => 1 << 32,
+        }
+    }
+
+    // First address past the zone.
+    #[inline]
+    pub fn end(self) -> u64 {
+        match self {
+            Zone::Dma => 1 << 24,
+            Zone::Dma32 => 1 << 32,
+            Zone::Normal => u64::MAX,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Zone::Dma => "DMA",
+            Zone::Dma32 => "DMA32",
+            Zone::Normal => "Normal",
+        }
+    }
+
+    pub fn of(addr: PhysAddr) -> Zone {
+        let addr: u64 = addr.into();
+
+        Zone::ALL.into_iter().find(|zone| addr < zone.end()).unwrap_or(Zone::Normal)
+    }
+
+    // Highest zone with memory below `limit`, where allocations constrained by it start.
+    pub fn highest_below(limit: PhysAddr) -> Zone {
+        let limit: u64 = limit.into();
+
+        Zone::ALL.into_iter().rev().find(|zone| zone.start() < limit).unwrap_or(Zone::Dma)
+    }
+
+    // Zone to try when this one is out of memory, scarcer zones are only used as a last resort.
+    #[inline]
+    pub fn fallback(self) -> Option<Zone> {
+        match self {
+            Zone::Normal => Some(Zone::Dma32),
+            Zone::Dma32 => Some(Zone::Dma),
+            Zone::Dma => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::generic::memory::{address::PhysAddr, allocators::physical::zone::Zone};
+
+    #[test]
+    fn zone_of_address() {
+        assert_eq!(Zone::of(PhysAddr::from(0x10_0000)), Zone::Dma);
+        assert_eq!(Zone::of(PhysAddr::from(0xFF_FFFF)), Zone::Dma);
+        assert_eq!(Zone::of(PhysAddr::from(0x100_0000)), Zone::Dma32);
+        assert_eq!(Zone::of(PhysAddr::from(0xFFFF_F000)), Zone::Dma32);
+        assert_eq!(Zone::of(PhysAddr::from(0x1_0000_0000)), Zone::Normal);
+    }
+
+    #[test]
+    fn limits_and_fallbacks() {
+        assert_eq!(Zone::highest_below(PhysAddr::from(1 << 24)), Zone::Dma);
+        assert_eq!(Zone::highest_below(PhysAddr::from(1 << 32)), Zone::Dma32);
+        assert_eq!(Zone::highest_below(PhysAddr::from(0xF_FFFF_FFFF)), Zone::Normal);
+        assert_eq!(Zone::Normal.fallback(), Some(Zone::Dma32));
+        assert_eq!(Zone::Dma32.fallback(), Some(Zone::Dma));
+        assert_eq!(Zone::Dma.fallback(), None);
+    }
+}
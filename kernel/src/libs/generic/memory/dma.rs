@@ -0,0 +1,100 @@
+use crate::libs::{
+    arch::{self, x86_64::memory::paging::{MemoryType, PageEntryFlags}},
+    generic::memory::{
+        address::{PhysAddr, VirtAddr},
+        allocators::physical::pfa::PageFrameAllocator,
+        vm::VmError,
+        FRAME_ALLOCATOR, KERNEL_ADDRESS_SPACE,
+    },
+};
+
+// Address limits for devices that can't reach the whole physical memory.
+pub const DMA_LIMIT_24BIT: u64 = 1 << 24;
+pub const DMA_LIMIT_32BIT: u64 = 1 << 32;
+pub const DMA_NO_LIMIT: u64 = u64::MAX;
+
+// How the HHDM maps memory, restored once an uncached buffer is freed.
+const HHDM_FLAGS: PageEntryFlags = PageEntryFlags::Present
+    .union(PageEntryFlags::ReadWrite)
+    .union(PageEntryFlags::ExecuteDisabled);
+
+// Physically contiguous, zeroed memory shared with a device, accessed through the HHDM.
+// The frames are given back when the buffer is dropped.
+pub struct DmaBuffer {
+    phys: PhysAddr,
+    size: usize,
+    uncached: bool,
+}
+
+impl DmaBuffer {
+    // `alignment` is in bytes, `limit` is the first physical address the device can't reach.
+    // Uncached buffers have their HHDM pages switched to uncached, for devices that don't snoop the caches.
+    pub fn allocate(size: usize, alignment: usize, limit: u64, uncached: bool) -> Result<Self, VmError> {
+        let phys = FRAME_ALLOCATOR
+            .lock()
+            .allocate_below(size, alignment, PhysAddr::from(limit), true)?;
+        let mut buffer = Self {
+            phys,
+            size,
+            uncached: false,
+        };
+
+        if uncached {
+            buffer.set_memory_type(MemoryType::Uncached)?;
+            buffer.uncached = true;
+        }
+        Ok(buffer)
+    }
+
+    fn set_memory_type(&self, memory_type: MemoryType) -> Result<(), VmError> {
+        let mut space = KERNEL_ADDRESS_SPACE.lock();
+        let space = space.as_mut().expect("DMA buffers can't be allocated before memory::init.");
+
+        space
+            .page_table()
+            .protect(&mut *FRAME_ALLOCATOR.lock(), self.virt(), self.size, HHDM_FLAGS | memory_type.flags())?;
+        Ok(())
+    }
+
+    // Address to hand over to the device.
+    #[inline]
+    pub fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    #[inline]
+    pub fn virt(&self) -> VirtAddr {
+        self.phys.as_hhdm()
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    #[inline]
+    pub fn is_uncached(&self) -> bool {
+        self.uncached
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.virt().as_mut_ptr::<u8>(), self.size) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.virt().as_mut_ptr::<u8>(), self.size) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        if self.uncached {
+            self.set_memory_type(MemoryType::WriteBack)
+                .expect("Failed to restore the caching of a DMA buffer.");
+        }
+
+        FRAME_ALLOCATOR
+            .lock()
+            .free(self.phys, self.size.max(1).div_ceil(arch::paging::get_page_frame_size()));
+    }
+}
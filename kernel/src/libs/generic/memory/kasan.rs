@@ -0,0 +1,308 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{
+    _log, debug,
+    libs::{
+        arch::{self, x86_64::memory::paging::PageEntryFlags},
+        generic::memory::{
+            address::{PhysAddr, VirtAddr},
+            allocators::{global, physical::pfa::PageFrameAllocator},
+            paging::PageTable,
+            vm::{Backing, VmError},
+            FRAME_ALLOCATOR, KERNEL_ADDRESS_SPACE,
+        },
+    },
+    warning,
+};
+
+// Every shadow byte describes 8 bytes of memory. 0 means all of them are accessible,
+// 1 to 7 that only the first N are, anything else that none are and why.
+const GRANULE_SHIFT: u64 = 3;
+const GRANULE_SIZE: u64 = 1 << GRANULE_SHIFT;
+
+pub const HEAP_REDZONE: u8 = 0xFC;
+pub const HEAP_FREED: u8 = 0xFB;
+
+const SHADOW_FLAGS: PageEntryFlags = PageEntryFlags::Present
+    .union(PageEntryFlags::ReadWrite)
+    .union(PageEntryFlags::ExecuteDisabled);
+
+// Memory described by the shadow, only the HHDM (which holds the heap) is covered.
+// Shadow pages are mapped the first time something is poisoned in them, until then
+// they are skipped and everything they describe is accessible.
+struct Shadow {
+    start: u64,
+    end: u64,
+    base: u64,
+    page_size: u64,
+    // One bit per shadow page, set once it is mapped.
+    mapped: *mut u64,
+}
+
+static mut SHADOW: Shadow = Shadow {
+    start: 0,
+    end: 0,
+    base: 0,
+    page_size: 0,
+    mapped: core::ptr::null_mut(),
+};
+static READY: AtomicBool = AtomicBool::new(false);
+// Checks are skipped while the heap updates its own metadata inside poisoned blocks.
+static SUSPENDED: AtomicUsize = AtomicUsize::new(0);
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+// Reserve the shadow of the first `length` bytes of the HHDM, every byte starts accessible.
+pub fn init(length: u64) -> Result<(), VmError> {
+    let pfsize = arch::paging::get_page_frame_size();
+    let start: u64 = PhysAddr::from(0).as_hhdm().into();
+    let shadow_size = (length >> GRANULE_SHIFT) as usize;
+    let mapped = FRAME_ALLOCATOR
+        .lock()
+        .allocate_contiguous_range(shadow_size.div_ceil(pfsize).div_ceil(64) * size_of::<u64>(), true)?;
+    let base = KERNEL_ADDRESS_SPACE
+        .lock()
+        .as_mut()
+        .expect("KASAN can't be set up before memory::init.")
+        .allocate(shadow_size, pfsize, SHADOW_FLAGS, Backing::Demand)?;
+
+    unsafe {
+        SHADOW = Shadow {
+            start,
+            end: start + length,
+            base: base.into(),
+            page_size: pfsize as u64,
+            mapped: mapped.as_hhdm().as_mut_ptr::<u64>(),
+        };
+    }
+    READY.store(true, Ordering::Release);
+    debug!("KASAN: shadow of {}MiB at 0x{:02x} ({}KiB, mapped on use)", length / 1024 / 1024, base, shadow_size / 1024);
+    Ok(())
+}
+
+// Stops checking until the guard is dropped.
+pub struct Suspended;
+
+#[sanitize(address = "off")]
+pub fn suspend() -> Suspended {
+    SUSPENDED.fetch_add(1, Ordering::Acquire);
+    Suspended
+}
+
+impl Drop for Suspended {
+    #[sanitize(address = "off")]
+    fn drop(&mut self) {
+        SUSPENDED.fetch_sub(1, Ordering::Release);
+    }
+}
+
+// Offset of the shadow byte of `addr` in the shadow, None if the address isn't covered.
+#[inline]
+#[sanitize(address = "off")]
+fn shadow_offset(addr: u64) -> Option<u64> {
+    if !READY.load(Ordering::Acquire) {
+        return None;
+    }
+    unsafe {
+        if addr < SHADOW.start || addr >= SHADOW.end {
+            return None;
+        }
+        Some((addr - SHADOW.start) >> GRANULE_SHIFT)
+    }
+}
+
+#[inline]
+#[sanitize(address = "off")]
+fn is_mapped(page: u64) -> bool {
+    unsafe { SHADOW.mapped.add((page / 64) as usize).read() & (1 << (page % 64)) != 0 }
+}
+
+// Shadow byte of `addr`, None if the address isn't covered or its shadow page was never mapped.
+// The shadow is read on every access, it must never fault: the fault handler would need
+// the locks held by the code being checked.
+#[inline]
+#[sanitize(address = "off")]
+fn shadow_of(addr: u64) -> Option<*mut u8> {
+    match shadow_offset(addr) {
+        Some(offset) if is_mapped(offset / unsafe { SHADOW.page_size }) => {
+            Some(unsafe { (SHADOW.base + offset) as *mut u8 })
+        }
+        _ => None,
+    }
+}
+
+// Map a shadow page straight in the kernel page table. The kernel address space may be held
+// by whoever is allocating from the heap, the frame allocator never is.
+#[sanitize(address = "off")]
+fn map_shadow_page(page: u64) -> bool {
+    let Ok(virt_addr) = VirtAddr::try_from(unsafe { SHADOW.base + page * SHADOW.page_size }) else {
+        return false;
+    };
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let Ok(frame) = allocator.allocate(true) else {
+        return false;
+    };
+    let mut page_table = PageTable::new(arch::paging::get_page_table_addr(), arch::paging::get_max_level());
+
+    if page_table.map_page(&mut *allocator, frame, virt_addr, SHADOW_FLAGS).is_err() {
+        allocator.free(frame, 1);
+        return false;
+    }
+    unsafe {
+        let word = SHADOW.mapped.add((page / 64) as usize);
+
+        word.write(word.read() | (1 << (page % 64)));
+    }
+    true
+}
+
+// Set the shadow byte of `addr`. Unmapped shadow pages read as accessible, they are only
+// mapped to store something else. Out of memory drops the value, the check is lost.
+#[inline]
+#[sanitize(address = "off")]
+fn set_shadow(addr: u64, value: u8) {
+    let Some(offset) = shadow_offset(addr) else {
+        return;
+    };
+    let page = offset / unsafe { SHADOW.page_size };
+
+    if !is_mapped(page) && (value == 0 || !map_shadow_page(page)) {
+        return;
+    }
+    unsafe { ((SHADOW.base + offset) as *mut u8).write(value) };
+}
+
+// Iterators and closures are generic code instantiated here and would be instrumented,
+// calling back into the hooks. Everything below sticks to plain loops.
+
+// Mark the granules fully inside [addr, addr + size) with `value`.
+#[sanitize(address = "off")]
+pub fn poison(addr: *const u8, size: usize, value: u8) {
+    let mut granule = (addr as u64).next_multiple_of(GRANULE_SIZE);
+    let end = (addr as u64 + size as u64) & !(GRANULE_SIZE - 1);
+
+    while granule < end {
+        set_shadow(granule, value);
+        granule += GRANULE_SIZE;
+    }
+}
+
+// Make [addr, addr + size) accessible, `addr` must be at the start of a granule.
+#[sanitize(address = "off")]
+pub fn unpoison(addr: *const u8, size: usize) {
+    let mut granule = addr as u64;
+    let end = granule + size as u64;
+
+    while granule < end {
+        let accessible = end - granule;
+
+        set_shadow(granule, if accessible >= GRANULE_SIZE { 0 } else { accessible as u8 });
+        granule += GRANULE_SIZE;
+    }
+}
+
+// First byte of the access that isn't accessible, with its shadow byte.
+#[sanitize(address = "off")]
+fn first_poisoned(addr: u64, size: usize) -> Option<(u64, u8)> {
+    let mut byte = addr;
+
+    while byte < addr + size as u64 {
+        if let Some(shadow) = shadow_of(byte) {
+            let shadow = unsafe { shadow.read() };
+
+            // Partially accessible granules hold the number of accessible bytes, poisoned ones are negative.
+            if shadow != 0 && (byte & (GRANULE_SIZE - 1)) as i8 >= shadow as i8 {
+                return Some((byte, shadow));
+            }
+        }
+        byte += 1;
+    }
+    None
+}
+
+// Inlined into the hooks, which aren't instrumented.
+#[inline(always)]
+fn check(addr: usize, size: usize, write: bool, caller: usize) {
+    if SUSPENDED.load(Ordering::Relaxed) != 0 {
+        return;
+    }
+    if let Some((bad, shadow)) = first_poisoned(addr as u64, size) {
+        report(addr as u64, size, write, caller, bad, shadow);
+    }
+}
+
+#[sanitize(address = "off")]
+fn report(addr: u64, size: usize, write: bool, caller: usize, bad: u64, shadow: u8) {
+    // Reporting runs instrumented code, which must not report again.
+    if REPORTING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    warning!(
+        "KASAN: {} of {} bytes at {:#x} from {:#x}",
+        if write { "write" } else { "read" },
+        size,
+        addr,
+        caller
+    );
+    _log!(
+        "",
+        "        First bad byte {:#x}, shadow {:#04x} ({})",
+        bad,
+        shadow,
+        match shadow {
+            HEAP_REDZONE => "heap red zone",
+            HEAP_FREED => "use after free",
+            1..=7 => "past the end of a heap allocation",
+            _ => "unknown",
+        }
+    );
+    global::describe_allocation(bad as usize);
+    REPORTING.store(false, Ordering::Release);
+}
+
+macro_rules! access_hooks {
+    ($($size:literal => $load:ident, $store:ident;)*) => {
+        $(
+            #[unsafe(no_mangle)]
+            #[inline(never)]
+            #[sanitize(address = "off")]
+            pub extern "C" fn $load(addr: usize) {
+                check(addr, $size, false, core::arch::return_address!() as usize);
+            }
+
+            #[unsafe(no_mangle)]
+            #[inline(never)]
+            #[sanitize(address = "off")]
+            pub extern "C" fn $store(addr: usize) {
+                check(addr, $size, true, core::arch::return_address!() as usize);
+            }
+        )*
+    };
+}
+
+// Called by the compiler instrumentation before every memory access.
+access_hooks! {
+    1 => __asan_load1_noabort, __asan_store1_noabort;
+    2 => __asan_load2_noabort, __asan_store2_noabort;
+    4 => __asan_load4_noabort, __asan_store4_noabort;
+    8 => __asan_load8_noabort, __asan_store8_noabort;
+    16 => __asan_load16_noabort, __asan_store16_noabort;
+}
+
+#[unsafe(no_mangle)]
+#[inline(never)]
+#[sanitize(address = "off")]
+pub extern "C" fn __asan_loadN_noabort(addr: usize, size: usize) {
+    check(addr, size, false, core::arch::return_address!() as usize);
+}
+
+#[unsafe(no_mangle)]
+#[inline(never)]
+#[sanitize(address = "off")]
+pub extern "C" fn __asan_storeN_noabort(addr: usize, size: usize) {
+    check(addr, size, true, core::arch::return_address!() as usize);
+}
+
+// Called before functions that never return, only needed when stacks are instrumented.
+#[unsafe(no_mangle)]
+#[sanitize(address = "off")]
+pub extern "C" fn __asan_handle_no_return() {}
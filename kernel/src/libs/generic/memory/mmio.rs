@@ -0,0 +1,100 @@
+use crate::libs::{
+    arch::{
+        self,
+        x86_64::memory::paging::{MemoryType, PageEntryFlags},
+    },
+    generic::memory::{
+        address::{PhysAddr, VirtAddr},
+        vm::{Backing, VmError},
+        KERNEL_ADDRESS_SPACE,
+    },
+};
+
+// Device memory mapped in the kernel address space, unmapped when dropped.
+pub struct IoMapping {
+    // Start of the mapped pages, the registers may start further in the first page.
+    base: VirtAddr,
+    offset: usize,
+    phys: PhysAddr,
+    length: usize,
+}
+
+// Map `length` bytes of device memory starting at `phys`, which doesn't need to be page aligned.
+// Device registers usually want MemoryType::Uncached.
+pub fn ioremap(phys: PhysAddr, length: usize, memory_type: MemoryType) -> Result<IoMapping, VmError> {
+    let pfsize = arch::paging::get_page_frame_size();
+    let start = phys.align_down(pfsize);
+    let offset = phys - start;
+    let mut space = KERNEL_ADDRESS_SPACE.lock();
+    let base = space
+        .as_mut()
+        .expect("Device memory can't be mapped before memory::init.")
+        .allocate(
+            (offset + length.max(1)).next_multiple_of(pfsize),
+            0,
+            PageEntryFlags::Present | PageEntryFlags::ReadWrite | PageEntryFlags::ExecuteDisabled | memory_type.flags(),
+            Backing::Physical(start),
+        )?;
+
+    Ok(IoMapping {
+        base,
+        offset,
+        phys,
+        length,
+    })
+}
+
+impl IoMapping {
+    #[inline]
+    pub fn virt(&self) -> VirtAddr {
+        self.base + self.offset
+    }
+
+    #[inline]
+    pub fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    #[inline]
+    fn register<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset + size_of::<T>() <= self.length,
+            "Access at offset {:#x} is outside of the device memory at 0x{:02x}",
+            offset,
+            self.phys
+        );
+        unsafe { (self.virt() + offset).as_mut_ptr::<T>() }
+    }
+
+    // Registers must be accessed with volatile operations of their exact size.
+    #[inline]
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { self.register::<T>(offset).read_volatile() }
+    }
+
+    #[inline]
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { self.register::<T>(offset).write_volatile(value) };
+    }
+}
+
+impl Drop for IoMapping {
+    fn drop(&mut self) {
+        KERNEL_ADDRESS_SPACE
+            .lock()
+            .as_mut()
+            .expect("Device memory unmapped without a kernel address space.")
+            .unmap(self.base)
+            .expect("Failed to unmap device memory.");
+    }
+}
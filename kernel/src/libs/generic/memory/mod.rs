@@ -1,5 +1,6 @@
 use crate::_log;
 use crate::debug;
+use crate::warning;
 use crate::libs::arch;
 use crate::libs::arch::paging::get_page_level_size;
 use crate::libs::arch::paging::get_page_table_addr;
@@ -9,50 +10,98 @@ use crate::libs::arch::x86_64::LD_RODATA_END;
 use crate::libs::arch::x86_64::LD_RODATA_START;
 use crate::libs::arch::x86_64::LD_TEXT_END;
 use crate::libs::arch::x86_64::LD_TEXT_START;
-use crate::libs::arch::x86_64::memory::paging::PageEntryFlags;
+use crate::libs::arch::x86_64::memory::paging::{MemoryType, PageEntryFlags};
+use crate::libs::generic::acpi::{slit, srat, AcpiTables};
 use crate::libs::generic::memory::address::PhysAddr;
 use crate::libs::generic::memory::address::VirtAddr;
-use crate::libs::generic::memory::allocators::physical::bump::BumpAllocator;
+use crate::libs::generic::memory::allocators::physical::bitmap::BitmapAllocator;
+use crate::libs::generic::memory::allocators::physical::numa::NumaAllocator;
 use crate::libs::generic::memory::allocators::physical::pfa::PageFrameAllocator;
+use crate::libs::generic::memory::allocators::physical::zone::Zone;
+use crate::libs::generic::memory::numa::NumaTopology;
 use crate::libs::generic::memory::paging::PageTable;
-use limine::{memory_map::EntryType, response::MemoryMapResponse};
+use crate::libs::generic::memory::paging::audit::AuditAction;
+use crate::libs::generic::memory::vm::{AddressSpace, Backing, Region};
+use limine::{memory_map::{Entry, EntryType}, response::MemoryMapResponse};
+use spin::Mutex;
 
 extern crate alloc;
 use alloc::vec::Vec;
 
 pub mod address;
+pub mod dma;
+#[cfg(feature = "kasan")]
+pub mod kasan;
+pub mod mmio;
+pub mod numa;
 pub mod paging;
+pub mod stack;
+pub mod vm;
 
 pub mod allocators {
     pub mod physical {
-        pub mod bump;
+        pub mod bitmap;
+        pub mod buddy;
+        pub mod numa;
         pub mod pfa;
+        pub mod zone;
     }
-    pub mod liballoc;
+    #[cfg(feature = "heap-debug")]
+    pub mod debug;
     pub mod global;
+    pub mod heap;
 }
 
-fn remap_kernel_section(new_pt: &mut PageTable, old_pt: &mut PageTable, section_address_start: VirtAddr, section_address_end: VirtAddr, flags: PageEntryFlags) {
+// Physical memory allocator used by the whole kernel once memory::init is done.
+pub static FRAME_ALLOCATOR: Mutex<NumaAllocator> = Mutex::new(NumaAllocator::new());
+
+// Higher half of the virtual memory, set up by memory::init.
+pub static KERNEL_ADDRESS_SPACE: Mutex<Option<AddressSpace>> = Mutex::new(None);
+
+// Copy of the memory map entries we can take back once the kernel is done with them.
+static RECLAIMABLE_MEMORY: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+// Last page of the address space, kept out so that region ends never overflow.
+const KERNEL_SPACE_END: u64 = 0xFFFF_FFFF_FFFF_F000;
+
+fn remap_kernel_section(allocator: &mut dyn PageFrameAllocator, new_pt: &mut PageTable, old_pt: &mut PageTable, section_address_start: VirtAddr, section_address_end: VirtAddr, flags: PageEntryFlags) -> PhysAddr {
     let pte = unsafe {
-        old_pt.get_pte::<BumpAllocator>(
+        old_pt.get_pte(
         section_address_start,
-        false,
+        None,
         PageEntryFlags::empty(),
         ).unwrap().read() };
     let offset = section_address_start.get_level_offset(paging::PaginationLevel::Physical);
-    let section_physical_addr: PhysAddr = unsafe {
-        PhysAddr::try_from(pte.get_address() + offset as usize).unwrap()
-    };
+    let section_physical_addr: PhysAddr = pte.get_address() + offset as usize;
 
-    new_pt.map_page_range::<BumpAllocator>(
+    new_pt.map_page_range(
+        allocator,
         section_physical_addr,
         section_address_start,
         flags,
-            (section_address_end - section_address_start).into()
-    );
+            section_address_end - section_address_start
+    ).expect("Failed to remap kernel section.");
+    section_physical_addr
+}
+
+// Node layout from the ACPI SRAT and SLIT, a single node if the firmware doesn't describe one.
+fn discover_topology(rsdp: Option<PhysAddr>) -> NumaTopology {
+    let mut topology = NumaTopology::uniform();
+    let Some(tables) = rsdp.and_then(|rsdp| unsafe { AcpiTables::new(rsdp) }) else {
+        warning!("No valid ACPI tables, assuming uniform memory.");
+        return topology;
+    };
+
+    if let Some(table) = tables.find(b"SRAT") {
+        srat::parse(table, &mut topology);
+        if let Some(table) = tables.find(b"SLIT") {
+            slit::parse(table, &mut topology);
+        }
+    }
+    topology
 }
 
-pub fn init(mmap: Option<&'static MemoryMapResponse>) {
+pub fn init(mmap: Option<&'static MemoryMapResponse>, rsdp: Option<PhysAddr>) {
     assert!(mmap.is_some());
     let entries: &[&limine::memory_map::Entry] = mmap.unwrap().entries();
 
@@ -81,37 +130,82 @@ pub fn init(mmap: Option<&'static MemoryMapResponse>) {
         );
     }
 
-    unsafe {
-        BumpAllocator::init(entries, crate::arch::paging::get_page_frame_size());
-    }
+    let mut boot_allocator = BitmapAllocator::new(entries, crate::arch::paging::get_page_frame_size());
+    let mut allocator = FRAME_ALLOCATOR.lock();
+
+    allocator.init(&mut boot_allocator, crate::arch::paging::get_page_frame_size(), discover_topology(rsdp));
+    let reclaimable = |entry_type: EntryType| -> u64 {
+        entries.iter()
+            .filter(|entry| entry.entry_type == entry_type && BitmapAllocator::is_reclaimable(entry, crate::arch::paging::get_page_frame_size()))
+            .map(|entry| entry.length)
+            .sum()
+    };
     debug!(
-        "Usable memory detected {}MiB",
-        BumpAllocator::available_total() / 1024 / 1024
+        "Usable memory detected {}MiB ({}MiB bootloader and {}MiB ACPI reclaimable later)",
+        allocator.available_total() / 1024 / 1024,
+        reclaimable(EntryType::BOOTLOADER_RECLAIMABLE) / 1024 / 1024,
+        reclaimable(EntryType::ACPI_RECLAIMABLE) / 1024 / 1024
     );
+    for zone in Zone::ALL {
+        _log!(
+            "",
+            "        Zone {}: {}MiB free",
+            zone.name(),
+            allocator.zone_free(zone) / 1024 / 1024
+        );
+    }
+    allocator.topology().dump();
+    if !allocator.topology().is_uniform() {
+        for node in 0..allocator.topology().node_count() {
+            _log!(
+                "",
+                "        Node {}: {}MiB free",
+                node,
+                (allocator.node(node).available_total() - allocator.node(node).used()) / 1024 / 1024
+            );
+        }
+    }
 
     let mut bootloader_pte = PageTable::new(get_page_table_addr(), crate::arch::paging::get_max_level());
     debug!("Bootloader page table: 0x{:02x}", get_page_table_addr());
 
-    let new_pt: PhysAddr = BumpAllocator::allocate_contiguous_range(get_page_level_size(), true);
+    let new_pt: PhysAddr = allocator
+        .allocate_contiguous_range(get_page_level_size(), true)
+        .expect("Failed to allocate the kernel page table.");
     debug!("New page table allocated at phys 0x{:02x}", new_pt);
 
     let sections: [(u64, u64, PageEntryFlags); 3] = [
-        (&raw const LD_TEXT_START as u64, &raw const LD_TEXT_END as u64, PageEntryFlags::from_bits_retain(39) ),
+        (&raw const LD_TEXT_START as u64, &raw const LD_TEXT_END as u64, PageEntryFlags::Accessed),
         (&raw const LD_RODATA_START as u64, &raw const LD_RODATA_END as u64, PageEntryFlags::ExecuteDisabled),
         (&raw const LD_DATA_START as u64, &raw const LD_DATA_END as u64, PageEntryFlags::Accessed | PageEntryFlags::Dirty | PageEntryFlags::ReadWrite | PageEntryFlags::ExecuteDisabled),
     ];
     let mut kernel_pt: PageTable = PageTable::new(new_pt, arch::paging::get_max_level());
+    let kernel_half = kernel_pt.kernel_half_index();
+
+    // User address spaces copy the kernel half of the top level table, it must never change.
+    kernel_pt
+        .preallocate_top_level(&mut *allocator, kernel_half)
+        .expect("Failed to allocate the kernel half of the page table.");
+
+    let mut kernel_regions: [Option<Region>; 3] = [None; 3];
 
     unsafe { VirtAddr::try_from(&raw const LD_TEXT_START as u64).unwrap().dump_offsets() };
-    for section in sections {
+    for (i, section) in sections.into_iter().enumerate() {
         let section_start: VirtAddr = VirtAddr::try_from(section.0).unwrap();
         let section_end: VirtAddr = VirtAddr::try_from(section.1).unwrap();
-
-        remap_kernel_section(
+        let section_phys_start = remap_kernel_section(
+            &mut *allocator,
             &mut kernel_pt,
             &mut bootloader_pte,
             section_start,
             section_end, section.2);
+
+        kernel_regions[i] = Some(Region::new(
+            section_start,
+            section_end - section_start,
+            section.2,
+            Backing::Physical(section_phys_start),
+        ));
     }
 
     debug!("Remapped kernel sections.");
@@ -123,25 +217,148 @@ pub fn init(mmap: Option<&'static MemoryMapResponse>) {
             entry.entry_type == EntryType::FRAMEBUFFER
     )
         .for_each(|section| {
-            kernel_pt.map_page_range::<BumpAllocator>(
+            kernel_pt.map_page_range(
+                &mut *allocator,
                 section.base.into(),
                 PhysAddr::from(section.base).as_hhdm(),
-                PageEntryFlags::Present | PageEntryFlags::ReadWrite,
-                section.length as usize);
+                PageEntryFlags::Present | PageEntryFlags::ReadWrite | PageEntryFlags::ExecuteDisabled | if section.entry_type == EntryType::FRAMEBUFFER {
+                    MemoryType::WriteCombining.flags()
+                } else {
+                    MemoryType::WriteBack.flags()
+                },
+                section.length as usize)
+                .expect("Failed to map memory in the HHDM.");
         });
+    let hhdm_length = entries.iter()
+        .map(|entry| entry.base + entry.length)
+        .max()
+        .unwrap_or(0);
+
     debug!("Mapped usable memory sections.");
+    #[cfg(feature = "paging-debug")]
+    kernel_pt.dump();
+    arch::paging::init_no_execute();
+    arch::paging::init_memory_types();
     kernel_pt.load();
     debug!("Loaded new page table, ready to allocate memory.");
 
     // It should be safe to allocate heap memory now
-    debug!("Memory used before allocations: {} MiB", BumpAllocator::used() / 1024 / 1024);
+    debug!("Memory used before allocations: {} MiB", allocator.used() / 1024 / 1024);
+    drop(allocator);
+
+    let mut kernel_space = AddressSpace::new(kernel_pt, arch::paging::get_kernel_space_start(), VirtAddr::try_from(KERNEL_SPACE_END).unwrap());
+
+    // The HHDM is only partially mapped, but nothing else should ever go in its window.
+    kernel_space.track(Region::new(
+        PhysAddr::from(0).as_hhdm(),
+        hhdm_length as usize,
+        PageEntryFlags::Present | PageEntryFlags::ReadWrite | PageEntryFlags::ExecuteDisabled,
+        Backing::Physical(PhysAddr::from(0)),
+    )).expect("Failed to track the HHDM region.");
+    for region in kernel_regions.into_iter().flatten() {
+        kernel_space.track(region).expect("Failed to track a kernel section.");
+    }
+    *KERNEL_ADDRESS_SPACE.lock() = Some(kernel_space);
+    audit_mappings(AuditAction::Panic);
+    #[cfg(feature = "kasan")]
+    kasan::init((boot_allocator.frames() * crate::arch::paging::get_page_frame_size()) as u64)
+        .expect("Failed to map the KASAN shadow.");
+    // The memory map lives in bootloader memory, keep what we need to reclaim it.
+    *RECLAIMABLE_MEMORY.lock() = entries.iter()
+        .filter(|entry| BitmapAllocator::is_reclaimable(entry, crate::arch::paging::get_page_frame_size()))
+        .map(|entry| **entry)
+        .collect();
     let mut vec: Vec<u64> = Vec::new();
 
     for i in 0..500000 {
         vec.push(i);
     }
     debug!("Heap test: Allocated vector with 500000 entries, last entry = {}", vec[499999]);
-    debug!("Memory used after allocations: {} MiB", BumpAllocator::used() / 1024 / 1024);
+    debug!("Memory used after allocations: {} MiB", FRAME_ALLOCATOR.lock().used() / 1024 / 1024);
     vec[0] = 42;
 
 }
+
+// Log how much memory the heap and the frame allocator use.
+#[cfg(not(test))]
+pub fn log_usage() {
+    let (allocated, reserved) = allocators::global::heap_usage();
+    let frames = FRAME_ALLOCATOR.lock();
+
+    warning!("Heap: {} KiB allocated, {} KiB reserved", allocated / 1024, reserved / 1024);
+    warning!(
+        "Page frames: {} MiB used out of {} MiB",
+        frames.used() / 1024 / 1024,
+        frames.available_total() / 1024 / 1024
+    );
+}
+
+// Check the page table loaded on this CPU against the W^X policy, returns the number of violations.
+pub fn audit_mappings(action: AuditAction) -> usize {
+    let hhdm = KERNEL_ADDRESS_SPACE
+        .lock()
+        .as_ref()
+        .and_then(|space| space.find(PhysAddr::from(0).as_hhdm()).map(|region| region.start.into()..region.end()))
+        .unwrap_or(0..0);
+    let page_table = PageTable::new(arch::paging::get_page_table_addr(), arch::paging::get_max_level());
+    let violations = page_table.audit(hhdm, &mut |range, violation| {
+        warning!(
+            "W^X: [{:#x} - {:#x}] -> {:#x}: {:?}",
+            range.virt,
+            Into::<u64>::into(range.virt).wrapping_add(range.length as u64),
+            range.phys,
+            violation
+        );
+    });
+
+    if violations == 0 {
+        debug!("W^X audit passed.");
+    } else if action == AuditAction::Panic {
+        panic!("{} mappings break the W^X policy.", violations);
+    }
+    violations
+}
+
+fn reclaim(entry_type: EntryType) -> usize {
+    let pfsize = arch::paging::get_page_frame_size() as u64;
+    let mut ranges: Vec<Entry> = Vec::new();
+
+    // Heap allocations lock the frame allocator, so the list is split before taking it.
+    RECLAIMABLE_MEMORY.lock().retain(|entry| {
+        if entry.entry_type == entry_type {
+            ranges.push(*entry);
+        }
+        entry.entry_type != entry_type
+    });
+
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let mut reclaimed = 0;
+
+    for entry in ranges.iter() {
+        let first = entry.base.div_ceil(pfsize);
+        let last = (entry.base + entry.length) / pfsize;
+
+        allocator.add_range(PhysAddr::from(first * pfsize), (last - first) as usize);
+        reclaimed += ((last - first) * pfsize) as usize;
+    }
+    debug!(
+        "Reclaimed {}MiB, usable memory is now {}MiB",
+        reclaimed / 1024 / 1024,
+        allocator.available_total() / 1024 / 1024
+    );
+    drop(allocator);
+    reclaimed
+}
+
+// Give the bootloader memory to the frame allocator, this includes the bootloader stack,
+// page tables and every Limine response.
+// Safety: nothing may reference them anymore, including the current stack.
+pub unsafe fn reclaim_bootloader_memory() -> usize {
+    reclaim(EntryType::BOOTLOADER_RECLAIMABLE)
+}
+
+// Give the memory holding the ACPI tables to the frame allocator.
+// Safety: the tables must have been parsed, and nothing may reference them anymore.
+pub unsafe fn reclaim_acpi_memory() -> usize {
+    reclaim(EntryType::ACPI_RECLAIMABLE)
+}
@@ -0,0 +1,241 @@
+use crate::{_log, debug, warning};
+
+// Nodes past this are folded into node 0.
+pub const MAX_NODES: usize = 8;
+const MAX_MEMORY_RANGES: usize = 32;
+const MAX_CPUS: usize = 256;
+
+// ACPI distances, a node is at distance 10 from itself.
+pub const LOCAL_DISTANCE: u8 = 10;
+pub const REMOTE_DISTANCE: u8 = 20;
+
+#[derive(Clone, Copy)]
+struct MemoryAffinity {
+    base: u64,
+    length: u64,
+    node: usize,
+}
+
+#[derive(Clone, Copy)]
+struct CpuAffinity {
+    apic_id: u32,
+    node: usize,
+}
+
+// Which node every memory range and CPU belongs to, and how far nodes are from each other.
+// Nodes are numbered in the order their ACPI proximity domains are found.
+// Memory and CPUs that aren't described belong to node 0.
+pub struct NumaTopology {
+    nodes: usize,
+    domains: [u32; MAX_NODES],
+    memory: [MemoryAffinity; MAX_MEMORY_RANGES],
+    memory_count: usize,
+    cpus: [CpuAffinity; MAX_CPUS],
+    cpu_count: usize,
+    distances: [[u8; MAX_NODES]; MAX_NODES],
+}
+
+impl NumaTopology {
+    // A single node holding everything, for machines without a SRAT.
+    pub const fn uniform() -> Self {
+        Self {
+            nodes: 1,
+            domains: [0; MAX_NODES],
+            memory: [MemoryAffinity { base: 0, length: 0, node: 0 }; MAX_MEMORY_RANGES],
+            memory_count: 0,
+            cpus: [CpuAffinity { apic_id: 0, node: 0 }; MAX_CPUS],
+            cpu_count: 0,
+            distances: [[LOCAL_DISTANCE; MAX_NODES]; MAX_NODES],
+        }
+    }
+
+    // Drop the default node, the SRAT describes every node including the first one.
+    pub fn clear(&mut self) {
+        *self = NumaTopology::uniform();
+        self.nodes = 0;
+    }
+
+    #[inline]
+    pub fn node_count(&self) -> usize {
+        self.nodes.max(1)
+    }
+
+    #[inline]
+    pub fn is_uniform(&self) -> bool {
+        self.node_count() == 1
+    }
+
+    pub fn domain(&self, node: usize) -> u32 {
+        self.domains[node]
+    }
+
+    pub fn node_of_domain(&self, domain: u32) -> Option<usize> {
+        self.domains[..self.nodes].iter().position(|x| *x == domain)
+    }
+
+    // Node of a proximity domain, created on first use.
+    fn add_domain(&mut self, domain: u32) -> usize {
+        if let Some(node) = self.node_of_domain(domain) {
+            return node;
+        }
+        if self.nodes == MAX_NODES {
+            warning!("Too many NUMA nodes, proximity domain {} is merged into node 0", domain);
+            return 0;
+        }
+        let node = self.nodes;
+
+        self.domains[node] = domain;
+        for other in 0..=node {
+            let distance = if other == node { LOCAL_DISTANCE } else { REMOTE_DISTANCE };
+
+            self.distances[node][other] = distance;
+            self.distances[other][node] = distance;
+        }
+        self.nodes += 1;
+        node
+    }
+
+    pub fn add_memory(&mut self, base: u64, length: u64, domain: u32) {
+        let node = self.add_domain(domain);
+
+        if self.memory_count == MAX_MEMORY_RANGES {
+            warning!("Too many NUMA memory ranges, [{:#x} - {:#x}] is left to node 0", base, base + length);
+            return;
+        }
+        self.memory[self.memory_count] = MemoryAffinity { base, length, node };
+        self.memory_count += 1;
+    }
+
+    pub fn add_cpu(&mut self, apic_id: u32, domain: u32) {
+        let node = self.add_domain(domain);
+
+        if self.cpu_count == MAX_CPUS {
+            warning!("Too many CPUs in the SRAT, APIC {} is left to node 0", apic_id);
+            return;
+        }
+        self.cpus[self.cpu_count] = CpuAffinity { apic_id, node };
+        self.cpu_count += 1;
+    }
+
+    // Distances are only known between domains that already have a node.
+    pub fn set_distance(&mut self, from: u32, to: u32, distance: u8) {
+        if let (Some(from), Some(to)) = (self.node_of_domain(from), self.node_of_domain(to)) {
+            self.distances[from][to] = distance;
+        }
+    }
+
+    #[inline]
+    pub fn distance(&self, from: usize, to: usize) -> u8 {
+        self.distances[from][to]
+    }
+
+    // Node owning `addr`, and the address where the next node may start.
+    pub fn node_extent(&self, addr: u64) -> (usize, u64) {
+        let ranges = &self.memory[..self.memory_count];
+
+        if let Some(range) = ranges.iter().find(|x| x.base <= addr && addr - x.base < x.length) {
+            return (range.node, range.base + range.length);
+        }
+        (0, ranges.iter().map(|x| x.base).filter(|x| *x > addr).min().unwrap_or(u64::MAX))
+    }
+
+    #[inline]
+    pub fn node_of_address(&self, addr: u64) -> usize {
+        self.node_extent(addr).0
+    }
+
+    pub fn node_of_cpu(&self, apic_id: u32) -> usize {
+        self.cpus[..self.cpu_count]
+            .iter()
+            .find(|x| x.apic_id == apic_id)
+            .map(|x| x.node)
+            .unwrap_or(0)
+    }
+
+    // Physical addresses covered by the SRAT ranges of a node, node 0 holds everything without them.
+    pub fn span(&self, node: usize, end: u64) -> Option<(u64, u64)> {
+        if self.memory_count == 0 {
+            return (node == 0).then_some((0, end));
+        }
+
+        let ranges = self.memory[..self.memory_count].iter().filter(|x| x.node == node && x.base < end);
+        let start = ranges.clone().map(|x| x.base).min()?;
+
+        Some((start, ranges.map(|x| (x.base + x.length).min(end)).max()?))
+    }
+
+    // Every node, nearest to `from` first. Returns the number of nodes written.
+    pub fn nodes_by_distance(&self, from: usize, order: &mut [usize; MAX_NODES]) -> usize {
+        let count = self.node_count();
+
+        for (i, node) in order.iter_mut().enumerate() {
+            *node = i;
+        }
+        order[..count].sort_unstable_by_key(|x| (*x != from, self.distances[from][*x], *x));
+        count
+    }
+
+    pub fn dump(&self) {
+        if self.is_uniform() {
+            debug!("NUMA: uniform memory, single node");
+            return;
+        }
+        debug!("NUMA: {} nodes", self.node_count());
+        for range in self.memory[..self.memory_count].iter() {
+            _log!(
+                "",
+                "        [{:#x} - {:#x}] node {} ({}MB)",
+                range.base,
+                range.base + range.length,
+                range.node,
+                range.length / 1024 / 1024
+            );
+        }
+        for node in 0..self.node_count() {
+            let cpus = self.cpus[..self.cpu_count].iter().filter(|x| x.node == node).count();
+
+            _log!(
+                "",
+                "        Node {} (domain {}): {} CPUs, distances {:?}",
+                node,
+                self.domains[node],
+                cpus,
+                &self.distances[node][..self.node_count()]
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::generic::memory::numa::{NumaTopology, MAX_NODES};
+
+    #[test]
+    fn nodes_from_domains() {
+        let mut topology = NumaTopology::uniform();
+        let mut order = [0; MAX_NODES];
+
+        topology.clear();
+        topology.add_memory(0, 0x8000_0000, 4);
+        topology.add_memory(0x8000_0000, 0x8000_0000, 7);
+        topology.add_memory(0x1_0000_0000, 0x4000_0000, 4);
+        topology.add_cpu(0, 4);
+        topology.add_cpu(1, 7);
+        topology.set_distance(4, 7, 21);
+        topology.set_distance(7, 4, 21);
+
+        assert_eq!(topology.node_count(), 2);
+        assert_eq!(topology.node_of_domain(7), Some(1));
+        assert_eq!(topology.node_of_address(0x9000_0000), 1);
+        assert_eq!(topology.node_of_address(0x1_0000_1000), 0);
+        assert_eq!(topology.node_extent(0x8000_0000), (1, 0x1_0000_0000));
+        assert_eq!(topology.node_of_cpu(1), 1);
+        assert_eq!(topology.distance(0, 1), 21);
+        assert_eq!(topology.span(1, u64::MAX), Some((0x8000_0000, 0x1_0000_0000)));
+        assert_eq!(topology.span(0, 0x1_2000_0000), Some((0, 0x1_2000_0000)));
+        assert_eq!(topology.span(0, 0x8000_0000), Some((0, 0x8000_0000)));
+        assert_eq!(NumaTopology::uniform().span(0, 0x4000_0000), Some((0, 0x4000_0000)));
+        assert_eq!(topology.nodes_by_distance(1, &mut order), 2);
+        assert_eq!(&order[..2], &[1, 0]);
+    }
+}
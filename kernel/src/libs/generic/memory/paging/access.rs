@@ -0,0 +1,19 @@
+use crate::libs::generic::memory::address::PhysAddr;
+
+// How page table code reaches the physical memory holding the tables.
+pub trait PhysicalMemory: Sync {
+    // Pointer through which the frame at `addr` can be read and written.
+    fn frame(&self, addr: PhysAddr) -> *mut u8;
+}
+
+// Physical memory seen through the HHDM, used by every kernel page table.
+pub struct Hhdm;
+
+impl PhysicalMemory for Hhdm {
+    #[inline]
+    fn frame(&self, addr: PhysAddr) -> *mut u8 {
+        unsafe { addr.as_hhdm().as_mut_ptr() }
+    }
+}
+
+pub static HHDM: Hhdm = Hhdm;
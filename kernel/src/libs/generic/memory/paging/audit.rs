@@ -0,0 +1,108 @@
+use core::ops::Range;
+
+use crate::libs::{
+    arch::{self, x86_64::memory::paging::PageEntryFlags},
+    generic::memory::paging::{walk::MappedRange, PageTable},
+};
+
+// Mapping breaking the W^X policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditViolation {
+    WritableExecutable,
+    // User accessible page in the kernel half.
+    UserKernelPage,
+    // The HHDM only holds data, nothing in it should be executable.
+    ExecutableHhdm,
+}
+
+// What to do once violations were reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Report,
+    Panic,
+}
+
+impl PageTable {
+    // Check every mapping against the W^X policy, `hhdm` being the virtual range of the HHDM.
+    // Each violating range is handed to `report`, returns the number of violations.
+    pub fn audit(&self, hhdm: Range<u64>, report: &mut dyn FnMut(&MappedRange, AuditViolation)) -> usize {
+        let kernel_start: u64 = arch::paging::get_kernel_space_start().into();
+        let mut violations = 0;
+
+        self.ranges(&mut |range| {
+            let start: u64 = range.virt.into();
+            let executable = !range.flags.contains(PageEntryFlags::ExecuteDisabled);
+            let checks = [
+                (executable && range.flags.contains(PageEntryFlags::ReadWrite), AuditViolation::WritableExecutable),
+                (start >= kernel_start && range.flags.contains(PageEntryFlags::User), AuditViolation::UserKernelPage),
+                (
+                    executable && start < hhdm.end && start.saturating_add(range.length as u64) > hhdm.start,
+                    AuditViolation::ExecutableHhdm,
+                ),
+            ];
+
+            for (_, violation) in checks.into_iter().filter(|(violated, _)| *violated) {
+                violations += 1;
+                report(range, violation);
+            }
+        });
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec::Vec;
+    use limine::paging::Mode;
+
+    use crate::{
+        libs::{
+            arch::{self, x86_64::memory::paging::PageEntryFlags},
+            generic::memory::{
+                address::{PhysAddr, VirtAddr},
+                allocators::physical::pfa::PageFrameAllocator,
+                paging::{
+                    audit::AuditViolation,
+                    sim::{SimulatedFrameAllocator, SimulatedMemory},
+                    PageTable, PaginationLevel,
+                },
+            },
+        },
+        KERNEL_CONTEXT,
+    };
+
+    #[test]
+    fn audit_reports_violations() {
+        unsafe { KERNEL_CONTEXT.boot_info.paging_level = Some(Mode::FOUR_LEVEL) };
+        let memory = SimulatedMemory::new(64);
+        let mut allocator = SimulatedFrameAllocator::new(memory);
+        let head = allocator.allocate_contiguous_range(arch::paging::get_page_level_size(), true).unwrap();
+        let mut table = PageTable::with_memory(head, PaginationLevel::Level4, memory);
+        let hhdm = VirtAddr::canonicalize(0xFFFF_8000_0000_0000, 48);
+        let text = VirtAddr::canonicalize(0xFFFF_FFFF_8000_0000, 48);
+        let data = PageEntryFlags::Present | PageEntryFlags::ReadWrite | PageEntryFlags::ExecuteDisabled;
+        let mut violations: Vec<AuditViolation> = Vec::new();
+
+        table.map_page_range(&mut allocator, PhysAddr::from(0), hhdm, data, 0x4000).unwrap();
+        table.map_page(&mut allocator, PhysAddr::from(0x10_0000), text, PageEntryFlags::Present).unwrap();
+        assert_eq!(table.audit(hhdm.into()..Into::<u64>::into(hhdm) + 0x4000, &mut |_, x| violations.push(x)), 0);
+
+        // Executable HHDM page, then a writable and user accessible kernel text page.
+        table.map_page(&mut allocator, PhysAddr::from(0x1000), hhdm + 0x1000, PageEntryFlags::Present).unwrap();
+        table
+            .map_page(
+                &mut allocator,
+                PhysAddr::from(0x10_1000),
+                text + 0x1000,
+                PageEntryFlags::Present | PageEntryFlags::ReadWrite | PageEntryFlags::User,
+            )
+            .unwrap();
+        assert_eq!(table.audit(hhdm.into()..Into::<u64>::into(hhdm) + 0x4000, &mut |_, x| violations.push(x)), 3);
+        assert_eq!(
+            violations,
+            [AuditViolation::ExecutableHhdm, AuditViolation::WritableExecutable, AuditViolation::UserKernelPage]
+        );
+    }
+}
@@ -1,22 +1,30 @@
 use limine::paging::Mode;
 
 use crate::{
-    debug, libs::{
+    libs::{
         arch::{
-            self, internal, paging::get_max_level, x86_64::memory::paging::{PageEntryFlags}
+            self, internal, x86_64::memory::paging::{PageEntryFlags}
         },
         generic::memory::{
-            address::*, allocators::physical::pfa::PageFrameAllocator,
-            paging::pmt::PageMapTableEntry,
+            address::*, allocators::physical::pfa::{FrameAllocatorError, PageFrameAllocator},
+            paging::{access::{PhysicalMemory, HHDM}, pmt::PageMapTableEntry, tlb::TlbFlush},
         },
     }
 };
 
+use core::ops::Range;
+
 use num_traits::PrimInt;
 
+pub mod access;
+pub mod audit;
 pub mod pmt;
+#[cfg(test)]
+pub mod sim;
+pub mod tlb;
+pub mod walk;
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum PaginationLevel {
     Physical = 0,
     Level1 = 1,
@@ -29,7 +37,17 @@ pub enum PaginationLevel {
 #[derive(Debug)]
 pub struct UnsupportedPaginationLevel;
 
-pub type UnmappedAddressError = ();
+#[derive(Debug)]
+pub enum PageTableError {
+    UnmappedAddress,
+    FrameAllocation(FrameAllocatorError),
+}
+
+impl From<FrameAllocatorError> for PageTableError {
+    fn from(value: FrameAllocatorError) -> Self {
+        PageTableError::FrameAllocation(value)
+    }
+}
 
 // TODO: Refactor, there's probably a way better way to do that lol.
 impl TryFrom<u64> for PaginationLevel {
@@ -78,70 +96,112 @@ impl TryFrom<Mode> for PaginationLevel {
 pub struct PageTable {
     pub head: PhysAddr,
     pub level: PaginationLevel,
+    memory: &'static dyn PhysicalMemory,
+    // The tables below the top level are shared with other page tables and must never be freed.
+    pinned: bool,
 }
 
 impl PageTable {
     pub fn new(head: PhysAddr, level: PaginationLevel) -> Self {
-        Self { head, level }
+        Self::with_memory(head, level, &HHDM)
+    }
+
+    // Page table whose tables are reached through `memory` instead of the HHDM.
+    pub fn with_memory(head: PhysAddr, level: PaginationLevel, memory: &'static dyn PhysicalMemory) -> Self {
+        Self {
+            head,
+            level,
+            memory,
+            pinned: false,
+        }
+    }
+
+    #[inline]
+    fn table(&self, addr: PhysAddr) -> *mut PageMapTableEntry {
+        self.memory.frame(addr) as *mut PageMapTableEntry
+    }
+
+    // Number of virtual address bits translated by this table.
+    #[inline]
+    pub fn address_bits(&self) -> u32 {
+        PageTable::level_size(self.level as u64).trailing_zeros() + 9
     }
 
     pub fn load(&self) {
         arch::paging::set_page_table_addr(self.head);
     }
 
-    pub fn dump(&self) {
-        unsafe {
-            for i in 0..512 {
-                let pm_ptr: *mut PageMapTableEntry = self.head.as_hhdm().as_mut_ptr::<PageMapTableEntry>().offset(i as isize);
-                let pm: PageMapTableEntry = *pm_ptr;
-
-                if pm.get_flags().contains(PageEntryFlags::Present) && i == 0x1ff {
-                    debug!("PMT L4 at {:02x} (index {:02x}): {}, bits: {}", pm_ptr.addr(), i, pm, pm.get_flags().bits());
-                    for j in 0..512 {
-                        let pm4: *mut PageMapTableEntry = ((pm.get_address().as_hhdm().as_mut_ptr()) as *mut PageMapTableEntry).offset(j);
-
-                        if (*pm4).get_flags().contains(PageEntryFlags::Present) && j == 0x1fe {
-                            debug!("     PDP at 0x{:02x} (index {:02x}): {}, bits {}", pm4.addr() as usize, j, *pm4, (*pm4).get_flags().bits());
-                            for k in 0..512 {
-                                let pdpe: *mut PageMapTableEntry = ((*pm4).get_address().as_hhdm().as_mut_ptr() as *mut PageMapTableEntry).offset(k);
-
-                                if k == 0x00 && (*pdpe).get_flags().contains(PageEntryFlags::Present) {
-                                    debug!("         PD at 0x{:02x} (index {:02x}): {}, bits {}", pdpe.addr(), k, *pdpe, (*pdpe).get_flags().bits());
-                                    for l in 0..512 {
-                                        let pde: *mut PageMapTableEntry = ((*pdpe).get_address().as_hhdm().as_mut_ptr() as *mut PageMapTableEntry).offset(l);
-
-                                        if l == 0x1d && (*pde).get_flags().contains(PageEntryFlags::Present) {
-                                            debug!("             PT at 0x{:02x}: {}, bits: {}", pde.addr(), *pde, (*pde).get_flags().bits());
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+    // Size of the memory mapped by a single entry of a given level.
+    #[inline]
+    pub fn level_size(level: u64) -> usize {
+        arch::paging::get_page_frame_size() << (9 * (level - 1))
+    }
+
+    // Walk down from the top level to the entry of `target` level for an address, stopping early
+    // on a missing entry or a huge page. Returns the last entry reached and its level,
+    // the entry we went through at each level is stored in `path`.
+    fn entry_at(
+        &self,
+        virt_addr: VirtAddr,
+        target: u64,
+        path: &mut [*mut PageMapTableEntry; 6],
+    ) -> (*mut PageMapTableEntry, u64) {
+        let mut head: *mut PageMapTableEntry = self.table(self.head);
+
+        for current_level in (target..(self.level as u64 + 1)).rev() {
+            unsafe {
+                let entry: *mut PageMapTableEntry = head.offset(virt_addr.get_level_offset(
+                    PaginationLevel::try_from(current_level).expect("Unknown pagination level."),
+                ) as isize);
+                let flags = (*entry).get_flags();
+
+                path[current_level as usize] = entry;
+                if current_level == target
+                    || !flags.contains(PageEntryFlags::Present)
+                    || (current_level > 1 && flags.contains(PageEntryFlags::HugePage))
+                {
+                    return (entry, current_level);
                 }
+                head = self.table((*entry).get_address());
             }
         }
+        unreachable!()
     }
 
     // TODO: Abstract, this is only valid for x86
-    // TODO: Support different flags for each entry
-    // Get the page table entry for a virtual address, if create is true
+    // Get the page table entry for a virtual address, if an allocator is given
     // we create the leaf at each level until L0 (physical offset)
-    pub fn get_pte<P: PageFrameAllocator>(
+    pub fn get_pte(
+        &mut self,
+        virt_addr: VirtAddr,
+        allocator: Option<&mut dyn PageFrameAllocator>,
+        flags: PageEntryFlags,
+    ) -> Result<*mut PageMapTableEntry, PageTableError> {
+        self.get_entry(virt_addr, allocator, flags, 1, &mut TlbFlush::new())
+    }
+
+    // Same as get_pte but stops at the entry of `level`, huge pages met on the way
+    // are split when an allocator is given.
+    fn get_entry(
         &mut self,
         virt_addr: VirtAddr,
-        allocate: bool,
+        mut allocator: Option<&mut dyn PageFrameAllocator>,
         flags: PageEntryFlags,
-    ) -> Result<*mut PageMapTableEntry, UnmappedAddressError> {
+        level: u64,
+        flush: &mut TlbFlush,
+    ) -> Result<*mut PageMapTableEntry, PageTableError> {
         // Note: We're trying to go from the top level (5 in modern x86_64),
         // ensure that the level has an entry at the given address offset
         // if not, allocate one, and repeat for the next level until we reach PTE.
-        let mut head: *mut PageMapTableEntry = unsafe { self.head.as_hhdm().as_mut_ptr() };
+        let mut head: *mut PageMapTableEntry = self.table(self.head);
+        // Permissions are enforced by the leaf entries, intermediate tables stay as permissive
+        // as the mappings below them need so that each page can be protected on its own.
+        let table_flags = PageEntryFlags::Present | PageEntryFlags::ReadWrite | (flags & PageEntryFlags::User);
 
         // debug!("Top level paging address: 0x{:02x}", head);
-        for current_level in (2..(get_max_level() as u64 + 1)).rev() {
+        for current_level in ((level + 1)..(self.level as u64 + 1)).rev() {
             unsafe {
-                let pm_ptr: *mut PageMapTableEntry = head as *mut PageMapTableEntry;
+                let pm_ptr: *mut PageMapTableEntry = head;
                 let current_level_offset = virt_addr.get_level_offset(
                     PaginationLevel::try_from(current_level).expect("Unknown pagination level."),
                 );
@@ -151,45 +211,370 @@ impl PageTable {
                     .get_flags()
                     .contains(PageEntryFlags::Present)
                 {
-                    if !allocate {
-                        return Err(());
-                    }
+                    let Some(allocator) = allocator.as_deref_mut() else {
+                        return Err(PageTableError::UnmappedAddress);
+                    };
                     /*debug!(
                         "Allocating for address 0x{:02x} as it is absent at level {}",
                         virt_addr, current_level
                     );*/
-                    let new_table_frame = P::allocate_contiguous_range(arch::paging::get_page_level_size(), true);
+                    let new_table_frame = allocator.allocate_contiguous_range(arch::paging::get_page_level_size(), true)?;
 
                     //debug!("New table frame at 0x{:02x}", new_table_frame);
                     (*pm_offset_ptr).set_address(new_table_frame.into());
-                    (*pm_offset_ptr).set_flags(
-                            PageEntryFlags::Present
-                            | flags,
-                    );
+                    (*pm_offset_ptr).replace_flags(table_flags);
+                } else if (*pm_offset_ptr).get_flags().contains(PageEntryFlags::HugePage) {
+                    let Some(allocator) = allocator.as_deref_mut() else {
+                        return Err(PageTableError::UnmappedAddress);
+                    };
+
+                    self.split(allocator, pm_offset_ptr, current_level, virt_addr, flush)?;
+                    (*pm_offset_ptr).set_flags(table_flags);
+                } else if allocator.is_some() {
+                    (*pm_offset_ptr).set_flags(table_flags);
                 }
                 /*debug!(
                     "Address 0x{:02x} ? Head 0x{:02x} Level {}, 0x{:02x}",
                     virt_addr, head, current_level, (*pm_offset_ptr).get_address()
                 );*/
-                head = (*pm_offset_ptr).get_address().as_hhdm().as_mut_ptr::<PageMapTableEntry>();
+                head = self.table((*pm_offset_ptr).get_address());
+            }
+        }
+        unsafe {
+            Ok(head.offset(virt_addr.get_level_offset(
+                PaginationLevel::try_from(level).expect("Unknown pagination level."),
+            ) as isize))
+        }
+    }
+
+    // Replace a huge page by a table of pages of the level below, mapping the same memory
+    // with the same permissions.
+    fn split(
+        &mut self,
+        allocator: &mut dyn PageFrameAllocator,
+        entry: *mut PageMapTableEntry,
+        level: u64,
+        virt_addr: VirtAddr,
+        flush: &mut TlbFlush,
+    ) -> Result<(), PageTableError> {
+        let table = allocator.allocate_contiguous_range(arch::paging::get_page_level_size(), false)?;
+        let entries: *mut PageMapTableEntry = self.table(table);
+
+        unsafe {
+            let base = (*entry).get_address();
+            let mut flags = (*entry).get_flags();
+
+            // At level 1 the huge page bit means PAT, small pages must not inherit it.
+            if level - 1 == 1 {
+                flags.remove(PageEntryFlags::HugePage);
+            }
+            for i in 0..512 {
+                let small = entries.add(i);
+
+                (*small).clear();
+                (*small).set_address((base + i * PageTable::level_size(level - 1)).into());
+                (*small).replace_flags(flags);
             }
+            (*entry).set_address(table.into());
+            (*entry).replace_flags(
+                PageEntryFlags::Present | PageEntryFlags::ReadWrite | (flags & PageEntryFlags::User),
+            );
         }
-        unsafe { Ok((head as *mut PageMapTableEntry).offset(virt_addr.get_level_offset(PaginationLevel::Level1) as isize)) }
+        flush.add(virt_addr);
+        Ok(())
     }
 
-    pub fn map_page<P: PageFrameAllocator>(
+    pub fn map_page(
         &mut self,
+        allocator: &mut dyn PageFrameAllocator,
         phys_addr: PhysAddr,
         virt_addr: VirtAddr,
         flags: PageEntryFlags,
-    ) {
-        let pte = self.get_pte::<P>(virt_addr, true, flags).unwrap();
+    ) -> Result<(), PageTableError> {
+        self.map_at_level(allocator, phys_addr, virt_addr, flags, 1, &mut TlbFlush::new())
+    }
+
+    // Map a page of the size of a `level` entry, both addresses must be aligned on it.
+    fn map_at_level(
+        &mut self,
+        allocator: &mut dyn PageFrameAllocator,
+        phys_addr: PhysAddr,
+        virt_addr: VirtAddr,
+        flags: PageEntryFlags,
+        level: u64,
+        flush: &mut TlbFlush,
+    ) -> Result<(), PageTableError> {
+        let pte = self.get_entry(virt_addr, Some(allocator), flags, level, flush)?;
+        let flags = if level > 1 {
+            flags | PageEntryFlags::HugePage
+        } else {
+            flags
+        };
 
         //debug!("Mapping phys 0x{:02x} to virt 0x{:02x} for PTE 0x{:02x}", phys_addr, virt_addr, pte.addr());
         unsafe {
+            let was_present = (*pte).get_flags().contains(PageEntryFlags::Present);
+
             (*pte).set_address(phys_addr.into());
-            (*pte).set_flags(flags | PageEntryFlags::Present);
+            (*pte).replace_flags(flags | PageEntryFlags::Present);
+            if was_present {
+                flush.add(virt_addr);
+            }
         };
+        Ok(())
+    }
+
+    // Largest level whose pages can map `length` bytes at these addresses, levels
+    // where a table already exists are skipped so that its mappings are not lost.
+    fn best_level(&self, phys_addr: PhysAddr, virt_addr: VirtAddr, length: usize) -> u64 {
+        let mut path: [*mut PageMapTableEntry; 6] = [core::ptr::null_mut(); 6];
+
+        for level in (2..(arch::paging::get_max_leaf_level() as u64 + 1)).rev() {
+            let size = PageTable::level_size(level) as u64;
+
+            if !phys_addr.is_aligned(size as usize)
+                || !virt_addr.is_aligned(size as usize)
+                || (length as u64) < size
+            {
+                continue;
+            }
+
+            let (entry, reached) = self.entry_at(virt_addr, level, &mut path);
+            let entry_flags = unsafe { (*entry).get_flags() };
+
+            if reached == level
+                && entry_flags.contains(PageEntryFlags::Present)
+                && !entry_flags.contains(PageEntryFlags::HugePage)
+            {
+                continue;
+            }
+            return level;
+        }
+        1
+    }
+
+    // Physical address and flags a virtual address is mapped to, if any.
+    pub fn translate(&self, virt_addr: VirtAddr) -> Option<(PhysAddr, PageEntryFlags)> {
+        let (pte, level) = self.entry_at(virt_addr, 1, &mut [core::ptr::null_mut(); 6]);
+        let pte = unsafe { *pte };
+
+        if !pte.get_flags().contains(PageEntryFlags::Present) {
+            return None;
+        }
+
+        let offset = Into::<u64>::into(virt_addr) as usize & (PageTable::level_size(level) - 1);
+
+        Some((pte.get_address() + offset, pte.get_flags()))
+    }
+
+    fn is_table_empty(&self, table: PhysAddr) -> bool {
+        let entries: *const PageMapTableEntry = self.table(table);
+
+        (0..512).all(|i| unsafe { !(*entries.offset(i)).get_flags().contains(PageEntryFlags::Present) })
+    }
+
+    // Give back the tables above `level` that were left empty, never the top level one.
+    fn free_empty_tables(
+        &self,
+        allocator: &mut dyn PageFrameAllocator,
+        path: &[*mut PageMapTableEntry; 6],
+        level: u64,
+    ) {
+        let table_frames = arch::paging::get_page_level_size() / arch::paging::get_page_frame_size();
+
+        for (parent, &entry) in path.iter().enumerate().take(self.level as usize + 1).skip(level as usize + 1) {
+            if parent == self.level as usize && self.pinned {
+                break;
+            }
+            unsafe {
+                if !self.is_table_empty((*entry).get_address()) {
+                    break;
+                }
+                allocator.free((*entry).get_address(), table_frames);
+                (*entry).clear();
+            }
+        }
+    }
+
+    // Top level entry where the kernel half starts.
+    #[inline]
+    pub fn kernel_half_index(&self) -> usize {
+        arch::paging::get_kernel_space_start().get_level_offset(self.level) as usize
+    }
+
+    // Give every top level entry from `first` on a table, and never free them. Page tables
+    // sharing these entries then see every mapping made below them.
+    pub fn preallocate_top_level(&mut self, allocator: &mut dyn PageFrameAllocator, first: usize) -> Result<(), PageTableError> {
+        let entries = self.table(self.head);
+
+        for index in first..512 {
+            unsafe {
+                let entry = entries.add(index);
+
+                if (*entry).get_flags().contains(PageEntryFlags::Present) {
+                    continue;
+                }
+
+                let table = allocator.allocate_contiguous_range(arch::paging::get_page_level_size(), true)?;
+
+                (*entry).set_address(table.into());
+                (*entry).replace_flags(PageEntryFlags::Present | PageEntryFlags::ReadWrite);
+            }
+        }
+        self.pinned = true;
+        Ok(())
+    }
+
+    // Copy the top level entries from `first` on, the tables below them become shared.
+    pub fn share_top_level(&mut self, other: &PageTable, first: usize) {
+        unsafe {
+            core::ptr::copy_nonoverlapping(other.table(other.head).add(first), self.table(self.head).add(first), 512 - first);
+        }
+    }
+
+    // Free the tables below the top level entries in `range`, the pages they map are left to the caller.
+    pub fn free_tables(&mut self, allocator: &mut dyn PageFrameAllocator, range: Range<usize>) {
+        let entries = self.table(self.head);
+
+        for index in range {
+            unsafe {
+                let entry = entries.add(index);
+
+                if (*entry).get_flags().contains(PageEntryFlags::Present) {
+                    self.free_table(allocator, (*entry).get_address(), self.level as u64 - 1);
+                }
+                (*entry).clear();
+            }
+        }
+    }
+
+    fn free_table(&self, allocator: &mut dyn PageFrameAllocator, table: PhysAddr, level: u64) {
+        let entries: *const PageMapTableEntry = self.table(table);
+
+        if level > 1 {
+            for index in 0..512 {
+                let entry = unsafe { *entries.add(index) };
+                let flags = entry.get_flags();
+
+                if flags.contains(PageEntryFlags::Present) && !flags.contains(PageEntryFlags::HugePage) {
+                    self.free_table(allocator, entry.get_address(), level - 1);
+                }
+            }
+        }
+        allocator.free(table, arch::paging::get_page_level_size() / arch::paging::get_page_frame_size());
+    }
+
+    // Remove the mapping of a page and return the frame it pointed to, the frame itself is
+    // left to the caller. Huge pages are split first, intermediate tables left empty are
+    // given back to the allocator.
+    pub fn unmap_page(
+        &mut self,
+        allocator: &mut dyn PageFrameAllocator,
+        virt_addr: VirtAddr,
+    ) -> Result<PhysAddr, PageTableError> {
+        let mut path: [*mut PageMapTableEntry; 6] = [core::ptr::null_mut(); 6];
+        let mut flush = TlbFlush::new();
+
+        loop {
+            let (pte, level) = self.entry_at(virt_addr, 1, &mut path);
+
+            unsafe {
+                if !(*pte).get_flags().contains(PageEntryFlags::Present) {
+                    return Err(PageTableError::UnmappedAddress);
+                }
+                if level > 1 {
+                    self.split(allocator, pte, level, virt_addr, &mut flush)?;
+                    continue;
+                }
+
+                let phys_addr = (*pte).get_address();
+
+                (*pte).clear();
+                flush.add(virt_addr);
+                self.free_empty_tables(allocator, &path, 1);
+                return Ok(phys_addr);
+            }
+        }
+    }
+
+    // Huge pages entirely covered by the range are dropped as a whole, the others are split.
+    pub fn unmap_page_range(
+        &mut self,
+        allocator: &mut dyn PageFrameAllocator,
+        virt_addr: VirtAddr,
+        length: usize,
+    ) -> Result<(), PageTableError> {
+        let length = Self::align_up(length, internal::memory::paging::get_page_frame_size());
+        let mut path: [*mut PageMapTableEntry; 6] = [core::ptr::null_mut(); 6];
+        let mut flush = TlbFlush::new();
+        let mut offset = 0;
+
+        while offset < length {
+            let (pte, level) = self.entry_at(virt_addr + offset, 1, &mut path);
+            let size = PageTable::level_size(level);
+
+            unsafe {
+                if !(*pte).get_flags().contains(PageEntryFlags::Present) {
+                    return Err(PageTableError::UnmappedAddress);
+                }
+                if level > 1 && (!(virt_addr + offset).is_aligned(size) || length - offset < size) {
+                    self.split(allocator, pte, level, virt_addr + offset, &mut flush)?;
+                    continue;
+                }
+                (*pte).clear();
+            }
+            flush.add(virt_addr + offset);
+            self.free_empty_tables(allocator, &path, level);
+            offset += size;
+        }
+        Ok(())
+    }
+
+    // Replace the permissions of every page in the range, the pages must already be mapped.
+    // Huge pages only partially covered by the range, or given the PAT bit, are split.
+    pub fn protect(
+        &mut self,
+        allocator: &mut dyn PageFrameAllocator,
+        virt_addr: VirtAddr,
+        length: usize,
+        flags: PageEntryFlags,
+    ) -> Result<(), PageTableError> {
+        let length = Self::align_up(length, internal::memory::paging::get_page_frame_size());
+        let mut path: [*mut PageMapTableEntry; 6] = [core::ptr::null_mut(); 6];
+        let mut flush = TlbFlush::new();
+        let mut offset = 0;
+
+        while offset < length {
+            let (pte, level) = self.entry_at(virt_addr + offset, 1, &mut path);
+            let size = PageTable::level_size(level);
+
+            unsafe {
+                if !(*pte).get_flags().contains(PageEntryFlags::Present) {
+                    return Err(PageTableError::UnmappedAddress);
+                }
+                if level > 1
+                    && (!(virt_addr + offset).is_aligned(size)
+                        || length - offset < size
+                        || flags.contains(PageEntryFlags::PageAttributeTable))
+                {
+                    self.split(allocator, pte, level, virt_addr + offset, &mut flush)?;
+                    continue;
+                }
+                if flags.contains(PageEntryFlags::User) {
+                    for entry in &path[(level as usize + 1)..(self.level as usize + 1)] {
+                        (**entry).set_flags(PageEntryFlags::User);
+                    }
+                }
+                (*pte).replace_flags(
+                    flags
+                        | PageEntryFlags::Present
+                        | if level > 1 { PageEntryFlags::HugePage } else { PageEntryFlags::empty() },
+                );
+            }
+            flush.add(virt_addr + offset);
+            offset += size;
+        }
+        Ok(())
     }
 
     #[inline]
@@ -199,22 +584,185 @@ impl PageTable {
         (value + mask) & !mask
     }
 
-    pub fn map_page_range<P: PageFrameAllocator>(
+    // Uses the largest pages the alignment and length of the range allow.
+    pub fn map_page_range(
         &mut self,
+        allocator: &mut dyn PageFrameAllocator,
         phys_addr: PhysAddr,
         virt_addr: VirtAddr,
         flags: PageEntryFlags,
         length: usize
-    ) {
+    ) -> Result<(), PageTableError> {
         let length = Self::align_up(length, internal::memory::paging::get_page_frame_size());
-        let step = internal::memory::paging::get_page_frame_size();
+        let mut flush = TlbFlush::new();
+        let mut offset = 0;
 
-        for i in (0..length).step_by(step) {
-            self.map_page::<P>(
-    phys_addr + i,
-    virt_addr + i,
-            flags
-            );
+        while offset < length {
+            // The PAT bit of huge pages is not where it is for small ones.
+            let level = if flags.contains(PageEntryFlags::PageAttributeTable) {
+                1
+            } else {
+                self.best_level(phys_addr + offset, virt_addr + offset, length - offset)
+            };
+
+            self.map_at_level(
+                allocator,
+                phys_addr + offset,
+                virt_addr + offset,
+                flags,
+                level,
+                &mut flush
+            )?;
+            offset += PageTable::level_size(level);
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec::Vec;
+
+    use crate::libs::{
+        arch::{self, x86_64::memory::paging::PageEntryFlags},
+        generic::memory::{
+            address::{PhysAddr, VirtAddr},
+            allocators::physical::pfa::PageFrameAllocator,
+            paging::{
+                sim::{SimulatedFrameAllocator, SimulatedMemory},
+                PageTable, PaginationLevel,
+            },
+        },
+    };
+
+    fn simulated_table(level: PaginationLevel) -> (PageTable, SimulatedFrameAllocator) {
+        let memory = SimulatedMemory::new(64);
+        let mut allocator = SimulatedFrameAllocator::new(memory);
+        let head = allocator
+            .allocate_contiguous_range(arch::paging::get_page_level_size(), true)
+            .unwrap();
+
+        (PageTable::with_memory(head, level, memory), allocator)
+    }
+
+    #[test]
+    fn map_translate_unmap() {
+        let (mut table, mut allocator) = simulated_table(PaginationLevel::Level4);
+        let virt = VirtAddr::canonicalize(0xFFFF_8000_1234_5000, 48);
+
+        table
+            .map_page(&mut allocator, PhysAddr::from(0x7000), virt, PageEntryFlags::Present | PageEntryFlags::ReadWrite)
+            .unwrap();
+        let (phys, flags) = table.translate(virt + 0x123).unwrap();
+
+        assert_eq!(phys, PhysAddr::from(0x7123));
+        assert!(flags.contains(PageEntryFlags::ReadWrite));
+        assert!(table.translate(virt + 0x1000).is_none());
+        assert_eq!(table.unmap_page(&mut allocator, virt).unwrap(), PhysAddr::from(0x7000));
+        assert!(table.translate(virt).is_none());
+        // The intermediate tables left empty were given back, only the top level one is left.
+        assert_eq!(allocator.used(), arch::paging::get_page_level_size());
+    }
+
+    #[test]
+    fn shared_top_level_tables() {
+        let memory = SimulatedMemory::new(1100);
+        let mut allocator = SimulatedFrameAllocator::new(memory);
+        let level_size = arch::paging::get_page_level_size();
+        let mut kernel = PageTable::with_memory(allocator.allocate_contiguous_range(level_size, true).unwrap(), PaginationLevel::Level4, memory);
+        let mut user = PageTable::with_memory(allocator.allocate_contiguous_range(level_size, true).unwrap(), PaginationLevel::Level4, memory);
+        let kernel_virt = VirtAddr::canonicalize(0xFFFF_8000_0000_0000, 48);
+        let user_virt = VirtAddr::canonicalize(0x40_0000, 48);
+        let flags = PageEntryFlags::Present | PageEntryFlags::ReadWrite;
+
+        kernel.preallocate_top_level(&mut allocator, 256).unwrap();
+        user.share_top_level(&kernel, 256);
+        // Mappings made in the kernel half after sharing show up in both tables.
+        kernel.map_page(&mut allocator, PhysAddr::from(0x5000), kernel_virt, flags).unwrap();
+        assert_eq!(user.translate(kernel_virt).unwrap().0, PhysAddr::from(0x5000));
+        kernel.unmap_page(&mut allocator, kernel_virt).unwrap();
+        kernel.map_page(&mut allocator, PhysAddr::from(0x6000), kernel_virt, flags).unwrap();
+        assert_eq!(user.translate(kernel_virt).unwrap().0, PhysAddr::from(0x6000));
+
+        let used = allocator.used();
+
+        user.map_page(&mut allocator, PhysAddr::from(0x7000), user_virt, flags | PageEntryFlags::User).unwrap();
+        assert!(kernel.translate(user_virt).is_none());
+        user.free_tables(&mut allocator, 0..256);
+        assert!(user.translate(user_virt).is_none());
+        assert!(user.translate(kernel_virt).is_some());
+        assert_eq!(allocator.used(), used);
+    }
+
+    #[test]
+    fn five_level_tables() {
+        let (mut table, mut allocator) = simulated_table(PaginationLevel::Level5);
+        // Only reachable with 57 bits addresses, it lives in the second L5 entry.
+        let virt = VirtAddr::canonicalize(0x0001_0000_0000_0000, 57);
+        let mut levels: Vec<u64> = Vec::new();
+
+        table
+            .map_page(&mut allocator, PhysAddr::from(0x9000), virt, PageEntryFlags::Present)
+            .unwrap();
+        assert_eq!(table.translate(virt).unwrap().0, PhysAddr::from(0x9000));
+        assert!(table.translate(VirtAddr::canonicalize(0, 57)).is_none());
+        assert_eq!(allocator.used(), 5 * arch::paging::get_page_level_size());
+
+        table.walk(&mut |entry| {
+            assert_eq!(entry.virt, virt);
+            levels.push(entry.level);
+        });
+        assert_eq!(levels, [5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn flags_propagate_to_parent_tables() {
+        let (mut table, mut allocator) = simulated_table(PaginationLevel::Level4);
+        let kernel = VirtAddr::canonicalize(0x40_0000, 48);
+        let user = kernel + 0x1000;
+        let mut parents = 0;
+
+        table
+            .map_page(&mut allocator, PhysAddr::from(0x1000), kernel, PageEntryFlags::Present | PageEntryFlags::ExecuteDisabled)
+            .unwrap();
+        table
+            .map_page(&mut allocator, PhysAddr::from(0x2000), user, PageEntryFlags::Present | PageEntryFlags::User)
+            .unwrap();
+        table.walk(&mut |entry| {
+            if !entry.is_leaf() {
+                // Tables allow whatever the pages below them need, the leaves restrict it.
+                assert!(entry.entry.get_flags().contains(PageEntryFlags::ReadWrite | PageEntryFlags::User));
+                parents += 1;
+            } else if entry.virt == kernel {
+                assert!(!entry.effective.contains(PageEntryFlags::User));
+                assert!(entry.effective.contains(PageEntryFlags::ExecuteDisabled));
+            } else {
+                assert!(entry.effective.contains(PageEntryFlags::User));
+                assert!(!entry.effective.contains(PageEntryFlags::ReadWrite));
+            }
+        });
+        assert_eq!(parents, 3);
+    }
+
+    #[test]
+    fn protect_splits_huge_pages() {
+        let (mut table, mut allocator) = simulated_table(PaginationLevel::Level4);
+        let virt = VirtAddr::canonicalize(0x20_0000, 48);
+        let flags = PageEntryFlags::Present | PageEntryFlags::ReadWrite;
+
+        table
+            .map_page_range(&mut allocator, PhysAddr::from(0x20_0000), virt, flags, 0x20_0000)
+            .unwrap();
+        assert!(table.translate(virt).unwrap().1.contains(PageEntryFlags::HugePage));
+
+        table.protect(&mut allocator, virt + 0x1000, 0x1000, PageEntryFlags::Present).unwrap();
+        let (phys, protected) = table.translate(virt + 0x1000).unwrap();
+
+        assert_eq!(phys, PhysAddr::from(0x20_1000));
+        assert!(!protected.contains(PageEntryFlags::ReadWrite));
+        assert!(table.translate(virt).unwrap().1.contains(PageEntryFlags::ReadWrite));
+        assert!(table.translate(virt + 0x2000).unwrap().1.contains(PageEntryFlags::ReadWrite));
     }
 }
@@ -23,6 +23,19 @@ impl PageMapTableEntry {
     pub fn set_flags(&mut self, flags: PageEntryFlags) {
         self.inner |= flags.bits();
     }
+
+    pub fn unset_flags(&mut self, flags: PageEntryFlags) {
+        self.inner &= !flags.bits();
+    }
+
+    // Replace every flag bit of the entry, keeping the address.
+    pub fn replace_flags(&mut self, flags: PageEntryFlags) {
+        self.inner = (self.inner & ADDRESS_MASK) | flags.bits();
+    }
+
+    pub fn clear(&mut self) {
+        self.inner = 0;
+    }
 }
 
 impl From<u64> for PageMapTableEntry {
@@ -0,0 +1,96 @@
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::libs::{
+    arch,
+    generic::memory::{
+        address::PhysAddr,
+        allocators::physical::pfa::{FrameAllocatorError, PageFrameAllocator},
+        paging::access::PhysicalMemory,
+    },
+};
+
+#[repr(C, align(4096))]
+struct SimulatedFrame([u8; 4096]);
+
+// Simulated RAM for host tests, physical addresses are offsets in a buffer.
+pub struct SimulatedMemory {
+    base: usize,
+    size: usize,
+}
+
+impl SimulatedMemory {
+    // The memory is leaked, page tables keep a 'static reference to it.
+    pub fn new(frames: usize) -> &'static Self {
+        let buffer: Vec<SimulatedFrame> = (0..frames).map(|_| SimulatedFrame([0; 4096])).collect();
+        let buffer = Box::leak(buffer.into_boxed_slice());
+
+        Box::leak(Box::new(Self {
+            base: buffer.as_mut_ptr() as usize,
+            size: frames * size_of::<SimulatedFrame>(),
+        }))
+    }
+}
+
+impl PhysicalMemory for SimulatedMemory {
+    fn frame(&self, addr: PhysAddr) -> *mut u8 {
+        let offset = Into::<u64>::into(addr) as usize;
+
+        assert!(offset < self.size, "Physical address 0x{:02x} is outside of the simulated memory", addr);
+        (self.base + offset) as *mut u8
+    }
+}
+
+// Hands out the frames of a SimulatedMemory in order, frees are only counted.
+pub struct SimulatedFrameAllocator {
+    memory: &'static SimulatedMemory,
+    next: usize,
+    used: usize,
+}
+
+impl SimulatedFrameAllocator {
+    // Frame 0 is kept out, like on real hardware.
+    pub fn new(memory: &'static SimulatedMemory) -> Self {
+        Self {
+            memory,
+            next: arch::paging::get_page_frame_size(),
+            used: 0,
+        }
+    }
+}
+
+impl PageFrameAllocator for SimulatedFrameAllocator {
+    fn allocate(&mut self, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        self.allocate_contiguous_range(arch::paging::get_page_frame_size(), clear)
+    }
+
+    fn allocate_contiguous_range(&mut self, size: usize, clear: bool) -> Result<PhysAddr, FrameAllocatorError> {
+        let size = size.max(1).next_multiple_of(arch::paging::get_page_frame_size());
+
+        if self.next + size > self.memory.size {
+            return Err(FrameAllocatorError::OutOfMemory);
+        }
+
+        let addr = PhysAddr::from(self.next as u64);
+
+        if clear {
+            unsafe { core::ptr::write_bytes(self.memory.frame(addr), 0, size) };
+        }
+        self.next += size;
+        self.used += size;
+        Ok(addr)
+    }
+
+    fn free(&mut self, _addr: PhysAddr, count: usize) {
+        self.used -= count * arch::paging::get_page_frame_size();
+    }
+
+    fn available_total(&self) -> usize {
+        self.memory.size
+    }
+
+    fn used(&self) -> usize {
+        self.used
+    }
+}
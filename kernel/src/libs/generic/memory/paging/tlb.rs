@@ -0,0 +1,151 @@
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::Mutex;
+
+use crate::libs::{arch, generic::memory::address::VirtAddr};
+
+// Past this many pages the whole TLB is flushed instead.
+const MAX_BATCHED_PAGES: usize = 32;
+
+// CPUs are tracked by APIC ID in 64 bit masks.
+const MAX_CPUS: u32 = 64;
+
+// CPUs running with the kernel page table that can take the shootdown IPI,
+// shootdowns are skipped while we're alone.
+static ONLINE_CPUS: AtomicU64 = AtomicU64::new(0);
+
+// Only one shootdown is in flight at a time, the request is read by the other CPUs
+// from their IPI handler while the initiator holds the lock.
+static SHOOTDOWN_LOCK: Mutex<()> = Mutex::new(());
+static mut SHOOTDOWN_REQUEST: Batch = Batch::new();
+// CPUs that haven't flushed the current request yet.
+static SHOOTDOWN_PENDING: AtomicU64 = AtomicU64::new(0);
+
+#[inline]
+fn cpu_bit() -> u64 {
+    1 << arch::current_cpu_id()
+}
+
+// Called by every CPU once it can service shootdowns, the other CPUs start sending it theirs.
+pub fn cpu_online() {
+    assert!(arch::current_cpu_id() < MAX_CPUS, "APIC ID {} is too high for TLB shootdowns", arch::current_cpu_id());
+    ONLINE_CPUS.fetch_or(cpu_bit(), Ordering::AcqRel);
+}
+
+#[derive(Clone, Copy)]
+struct Batch {
+    pages: [u64; MAX_BATCHED_PAGES],
+    count: usize,
+    all: bool,
+}
+
+impl Batch {
+    const fn new() -> Self {
+        Self {
+            pages: [0; MAX_BATCHED_PAGES],
+            count: 0,
+            all: false,
+        }
+    }
+
+    // Host tests run page tables in simulated memory, there is no TLB to flush.
+    #[cfg(test)]
+    fn flush_local(&self) {}
+
+    #[cfg(not(test))]
+    fn flush_local(&self) {
+        if self.all {
+            arch::paging::flush_tlb_global();
+            return;
+        }
+        for page in &self.pages[..self.count] {
+            arch::paging::flush_tlb_page(VirtAddr::try_from(*page).unwrap());
+        }
+    }
+}
+
+// Pending TLB invalidations, flushed on every online CPU when dropped.
+// Page table mutations collect the addresses they changed into one so that
+// a range update costs a single shootdown.
+pub struct TlbFlush {
+    batch: Batch,
+}
+
+impl Default for TlbFlush {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TlbFlush {
+    pub const fn new() -> Self {
+        Self { batch: Batch::new() }
+    }
+
+    pub fn add(&mut self, addr: VirtAddr) {
+        if self.batch.count == MAX_BATCHED_PAGES {
+            self.batch.all = true;
+            return;
+        }
+        self.batch.pages[self.batch.count] = addr.into();
+        self.batch.count += 1;
+    }
+
+    pub fn add_all(&mut self) {
+        self.batch.all = true;
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.batch.count == 0 && !self.batch.all
+    }
+
+    pub fn flush(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        self.batch.flush_local();
+
+        let others = ONLINE_CPUS.load(Ordering::Acquire) & !cpu_bit();
+
+        if others != 0 {
+            // We may run with interrupts disabled (page fault handler), while another CPU waits
+            // for us to take its IPI. Its request is serviced here instead.
+            let _guard = loop {
+                if let Some(guard) = SHOOTDOWN_LOCK.try_lock() {
+                    break guard;
+                }
+                handle_shootdown();
+                spin_loop();
+            };
+
+            unsafe { SHOOTDOWN_REQUEST = self.batch };
+            SHOOTDOWN_PENDING.store(others, Ordering::Release);
+            arch::paging::send_tlb_shootdown();
+            while SHOOTDOWN_PENDING.load(Ordering::Acquire) != 0 {
+                spin_loop();
+            }
+        }
+        self.batch = Batch::new();
+    }
+}
+
+impl Drop for TlbFlush {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// Called from the shootdown IPI handler on the other CPUs, does nothing if this CPU
+// already flushed the current request.
+pub fn handle_shootdown() {
+    let bit = cpu_bit();
+
+    if SHOOTDOWN_PENDING.load(Ordering::Acquire) & bit != 0 {
+        unsafe { SHOOTDOWN_REQUEST.flush_local() };
+        SHOOTDOWN_PENDING.fetch_and(!bit, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{
+    _log, debug,
+    libs::{
+        arch::{self, x86_64::memory::paging::PageEntryFlags},
+        generic::memory::{
+            address::{PhysAddr, VirtAddr},
+            paging::{pmt::PageMapTableEntry, PageTable},
+        },
+    },
+};
+
+// Flags that only mean something once every level of the walk is combined.
+const PERMISSIONS: PageEntryFlags = PageEntryFlags::ReadWrite
+    .union(PageEntryFlags::User)
+    .union(PageEntryFlags::ExecuteDisabled);
+
+// Present entry reached while walking a page table.
+pub struct WalkEntry {
+    // First address translated through the entry.
+    pub virt: VirtAddr,
+    pub level: u64,
+    pub entry: PageMapTableEntry,
+    // Entry flags, with the permissions restricted by every parent entry.
+    pub effective: PageEntryFlags,
+}
+
+impl WalkEntry {
+    // The entry maps memory instead of pointing to a lower level table.
+    #[inline]
+    pub fn is_leaf(&self) -> bool {
+        self.level == 1 || self.entry.get_flags().contains(PageEntryFlags::HugePage)
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        PageTable::level_size(self.level)
+    }
+}
+
+// Contiguous virtual memory mapped to contiguous physical memory with the same permissions.
+#[derive(Clone, Copy)]
+pub struct MappedRange {
+    pub virt: VirtAddr,
+    pub phys: PhysAddr,
+    pub length: usize,
+    pub flags: PageEntryFlags,
+}
+
+impl PageTable {
+    // Visit every present entry at every level in address order, tables after the entry pointing to them.
+    pub fn walk(&self, visitor: &mut dyn FnMut(&WalkEntry)) {
+        self.walk_table(
+            self.head,
+            self.level as u64,
+            0,
+            PageEntryFlags::ReadWrite | PageEntryFlags::User,
+            visitor,
+        );
+    }
+
+    fn walk_table(
+        &self,
+        table: PhysAddr,
+        level: u64,
+        base: u64,
+        inherited: PageEntryFlags,
+        visitor: &mut dyn FnMut(&WalkEntry),
+    ) {
+        let entries: *const PageMapTableEntry = self.table(table);
+        let size = PageTable::level_size(level) as u64;
+
+        for index in 0..(arch::paging::get_page_frame_size() / size_of::<PageMapTableEntry>()) {
+            let entry = unsafe { *entries.add(index) };
+            let flags = entry.get_flags();
+
+            if !flags.contains(PageEntryFlags::Present) {
+                continue;
+            }
+            // Writable and user accessible only if every level allows it, executable unless any level forbids it.
+            let effective = (flags - PERMISSIONS)
+                | (flags & inherited & (PageEntryFlags::ReadWrite | PageEntryFlags::User))
+                | ((flags | inherited) & PageEntryFlags::ExecuteDisabled);
+            let walk_entry = WalkEntry {
+                virt: VirtAddr::canonicalize(base + index as u64 * size, self.address_bits()),
+                level,
+                entry,
+                effective,
+            };
+
+            visitor(&walk_entry);
+            if !walk_entry.is_leaf() {
+                self.walk_table(entry.get_address(), level - 1, base + index as u64 * size, effective, visitor);
+            }
+        }
+    }
+
+    // Visit the leaf mappings, merging neighbours that continue each other.
+    pub fn ranges(&self, visitor: &mut dyn FnMut(&MappedRange)) {
+        let mut current: Option<MappedRange> = None;
+
+        self.walk(&mut |entry| {
+            if !entry.is_leaf() {
+                return;
+            }
+            let flags = entry.effective & PERMISSIONS;
+
+            if let Some(range) = &mut current {
+                let virt_end = Into::<u64>::into(range.virt).wrapping_add(range.length as u64);
+                let phys_end = Into::<u64>::into(range.phys) + range.length as u64;
+
+                if virt_end == entry.virt.into() && phys_end == entry.entry.get_address().into() && range.flags == flags {
+                    range.length += entry.size();
+                    return;
+                }
+                visitor(range);
+            }
+            current = Some(MappedRange {
+                virt: entry.virt,
+                phys: entry.entry.get_address(),
+                length: entry.size(),
+                flags,
+            });
+        });
+        if let Some(range) = current {
+            visitor(&range);
+        }
+    }
+
+    pub fn dump(&self) {
+        debug!("Page table at 0x{:02x} ({} levels):", self.head, self.level as u64);
+        self.ranges(&mut |range| {
+            _log!(
+                "",
+                "        [{:#x} - {:#x}] -> {:#x} ({}KiB) r{}{}{}",
+                range.virt,
+                Into::<u64>::into(range.virt).wrapping_add(range.length as u64),
+                range.phys,
+                range.length / 1024,
+                if range.flags.contains(PageEntryFlags::ReadWrite) { "w" } else { "-" },
+                if range.flags.contains(PageEntryFlags::ExecuteDisabled) { "-" } else { "x" },
+                if range.flags.contains(PageEntryFlags::User) { "u" } else { "k" }
+            );
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec::Vec;
+
+    use crate::libs::{
+        arch::{self, x86_64::memory::paging::PageEntryFlags},
+        generic::memory::{
+            address::{PhysAddr, VirtAddr},
+            allocators::physical::pfa::PageFrameAllocator,
+            paging::{
+                sim::{SimulatedFrameAllocator, SimulatedMemory},
+                walk::MappedRange,
+                PageTable, PaginationLevel,
+            },
+        },
+    };
+
+    #[test]
+    fn ranges_coalesce_contiguous_mappings() {
+        let memory = SimulatedMemory::new(64);
+        let mut allocator = SimulatedFrameAllocator::new(memory);
+        let head = allocator.allocate_contiguous_range(arch::paging::get_page_level_size(), true).unwrap();
+        let mut table = PageTable::with_memory(head, PaginationLevel::Level4, memory);
+        let virt = VirtAddr::canonicalize(0xFFFF_8000_0000_0000, 48);
+        let present = PageEntryFlags::Present | PageEntryFlags::ReadWrite;
+        let mut ranges: Vec<MappedRange> = Vec::new();
+
+        // A 2MiB page, followed by two 4KiB pages continuing it, then a read-only page.
+        table.map_page_range(&mut allocator, PhysAddr::from(0x20_0000), virt, present, 0x20_0000).unwrap();
+        table.map_page_range(&mut allocator, PhysAddr::from(0x40_0000), virt + 0x20_0000, present, 0x2000).unwrap();
+        table.map_page(&mut allocator, PhysAddr::from(0x40_2000), virt + 0x20_2000, PageEntryFlags::Present).unwrap();
+
+        table.ranges(&mut |range| ranges.push(*range));
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0].virt, virt);
+        assert_eq!(ranges[0].phys, PhysAddr::from(0x20_0000));
+        assert_eq!(ranges[0].length, 0x20_0000 + 0x2000);
+        assert!(ranges[0].flags.contains(PageEntryFlags::ReadWrite));
+        assert_eq!(ranges[1].virt, virt + 0x20_2000);
+        assert!(!ranges[1].flags.contains(PageEntryFlags::ReadWrite));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::libs::{
+    arch::{self, x86_64::memory::paging::PageEntryFlags},
+    generic::memory::{
+        address::VirtAddr,
+        vm::{Backing, Region, VmError},
+        KERNEL_ADDRESS_SPACE,
+    },
+};
+
+// Virtual window kernel stacks are allocated in, away from the HHDM and the kernel image.
+const STACKS_START: u64 = 0xFFFF_FE00_0000_0000;
+const STACKS_END: u64 = 0xFFFF_FF00_0000_0000;
+
+// Who a stack belongs to, reported when its guard pages are hit.
+#[derive(Debug, Clone, Copy)]
+pub enum StackOwner {
+    Thread(usize),
+    // Interrupt stack table slot of the current CPU.
+    Interrupt(u8),
+}
+
+// Kernel stack mapped in the kernel address space, with an unmapped guard page on each side.
+// Dropping it keeps it mapped, stacks are given back with free or kept for good with leak.
+#[must_use]
+pub struct KernelStack {
+    bottom: VirtAddr,
+    size: usize,
+    pub owner: StackOwner,
+}
+
+impl KernelStack {
+    pub fn allocate(size: usize, owner: StackOwner) -> Result<Self, VmError> {
+        let guard = arch::paging::get_page_frame_size();
+        let size = size.next_multiple_of(guard);
+        let mut space = KERNEL_ADDRESS_SPACE.lock();
+        let space = space.as_mut().expect("Kernel stacks can't be allocated before memory::init.");
+        let base = space
+            .find_free_between(STACKS_START, STACKS_END, size + 2 * guard, 0)
+            .ok_or(VmError::NoFreeRange)?;
+
+        space.track(Region::new(base, guard, PageEntryFlags::empty(), Backing::Guard(owner)))?;
+        if let Err(error) = space.map(Region::new(
+            base + guard,
+            size,
+            PageEntryFlags::Present | PageEntryFlags::ReadWrite | PageEntryFlags::ExecuteDisabled,
+            Backing::Anonymous,
+        )) {
+            space.unmap(base)?;
+            return Err(error);
+        }
+        if let Err(error) = space.track(Region::new(base + guard + size, guard, PageEntryFlags::empty(), Backing::Guard(owner))) {
+            space.unmap(base + guard)?;
+            space.unmap(base)?;
+            return Err(error);
+        }
+        Ok(Self {
+            bottom: base + guard,
+            size,
+            owner,
+        })
+    }
+
+    // Initial stack pointer, stacks grow down.
+    #[inline]
+    pub fn top(&self) -> VirtAddr {
+        self.bottom + self.size
+    }
+
+    #[inline]
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom
+    }
+
+    // Keep the stack for the rest of the kernel's life, returns its top.
+    pub fn leak(self) -> VirtAddr {
+        self.top()
+    }
+
+    pub fn free(self) -> Result<(), VmError> {
+        let guard = arch::paging::get_page_frame_size();
+        let mut space = KERNEL_ADDRESS_SPACE.lock();
+        let space = space.as_mut().expect("Kernel stacks can't be freed before memory::init.");
+
+        space.unmap(self.bottom - guard)?;
+        space.unmap(self.bottom)?;
+        space.unmap(self.top())?;
+        Ok(())
+    }
+}
+
+// Owner of the stack whose guard page holds `addr`. The address space isn't waited for,
+// this runs from the double fault handler.
+pub fn guard_owner(addr: VirtAddr) -> Option<StackOwner> {
+    let space = KERNEL_ADDRESS_SPACE.try_lock()?;
+
+    match space.as_ref()?.find(addr)?.backing {
+        Backing::Guard(owner) => Some(owner),
+        _ => None,
+    }
+}
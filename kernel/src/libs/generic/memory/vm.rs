@@ -0,0 +1,571 @@
+extern crate alloc;
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use spin::Mutex;
+
+use crate::libs::{
+    arch::{self, x86_64::memory::paging::PageEntryFlags},
+    generic::memory::{
+        address::{Page, PhysAddr, VirtAddr},
+        allocators::physical::pfa::{FrameAllocatorError, PageFrameAllocator},
+        paging::{PageTable, PageTableError},
+        stack::StackOwner,
+        FRAME_ALLOCATOR, KERNEL_ADDRESS_SPACE,
+    },
+};
+use crate::warning;
+
+// Lowest user address, the null page is never mapped.
+const USER_SPACE_START: u64 = 0x1000;
+
+#[derive(Debug)]
+pub enum VmError {
+    // The range is outside of the address space or collides with another region.
+    Overlap,
+    NoFreeRange,
+    NotFound,
+    // The access is not allowed by the region flags.
+    AccessViolation,
+    // The access hit the guard page of a kernel stack.
+    StackOverflow(StackOwner),
+    // The fault interrupted code holding a lock it needs, waiting for it would never end.
+    WouldDeadlock,
+    PageTable(PageTableError),
+}
+
+impl From<PageTableError> for VmError {
+    fn from(value: PageTableError) -> Self {
+        VmError::PageTable(value)
+    }
+}
+
+impl From<FrameAllocatorError> for VmError {
+    fn from(value: FrameAllocatorError) -> Self {
+        VmError::PageTable(PageTableError::FrameAllocation(value))
+    }
+}
+
+// What a region is backed by.
+#[derive(Clone, Copy)]
+pub enum Backing {
+    // Frames are allocated when the region is mapped and given back when it is unmapped.
+    Anonymous,
+    // Zeroed frames are allocated and mapped on the first access to each page.
+    Demand,
+    // Fixed physical memory (kernel image, HHDM, MMIO), never freed by the address space.
+    Physical(PhysAddr),
+    // Only keeps the range from being handed out, nothing is mapped.
+    Reserved,
+    // Never mapped page around a kernel stack, touching it is an overflow.
+    Guard(StackOwner),
+}
+
+// Page fault as decoded by the architecture.
+pub struct PageFault {
+    pub address: VirtAddr,
+    // The page was mapped, the fault comes from its permissions.
+    pub present: bool,
+    pub write: bool,
+    pub user: bool,
+    pub execute: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct Region {
+    pub start: VirtAddr,
+    pub length: usize,
+    pub flags: PageEntryFlags,
+    pub backing: Backing,
+}
+
+impl Region {
+    pub fn new(start: VirtAddr, length: usize, flags: PageEntryFlags, backing: Backing) -> Self {
+        Self {
+            start,
+            length: PageTable::align_up(length, arch::paging::get_page_frame_size()),
+            flags,
+            backing,
+        }
+    }
+
+    #[inline]
+    pub fn end(&self) -> u64 {
+        Into::<u64>::into(self.start) + self.length as u64
+    }
+
+    #[inline]
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        (Into::<u64>::into(self.start)..self.end()).contains(&addr.into())
+    }
+}
+
+// Virtual memory regions of an address space, sorted by start address, and the page table
+// they are mapped in. Page tables are filled from FRAME_ALLOCATOR, which must not be held
+// by the caller as the region tree itself lives on the heap.
+pub struct AddressSpace {
+    page_table: PageTable,
+    regions: BTreeMap<u64, Region>,
+    start: u64,
+    end: u64,
+    // Owns the lower half of its page table, everything is given back when it is dropped.
+    user: bool,
+}
+
+// User address space of the running thread, None while running kernel threads.
+static CURRENT_USER_SPACE: Mutex<Option<Arc<Mutex<AddressSpace>>>> = Mutex::new(None);
+
+impl AddressSpace {
+    pub fn new(page_table: PageTable, start: VirtAddr, end: VirtAddr) -> Self {
+        Self {
+            page_table,
+            regions: BTreeMap::new(),
+            start: start.into(),
+            end: end.into(),
+            user: false,
+        }
+    }
+
+    // Empty lower half with its own top level table, the kernel half is shared with the kernel address space.
+    pub fn new_user() -> Result<Self, VmError> {
+        let mut kernel_space = KERNEL_ADDRESS_SPACE.lock();
+        let kernel_pt = &kernel_space
+            .as_mut()
+            .expect("User address spaces can't be created before memory::init.")
+            .page_table;
+        let head = FRAME_ALLOCATOR
+            .lock()
+            .allocate_contiguous_range(arch::paging::get_page_level_size(), true)?;
+        let mut page_table = PageTable::new(head, kernel_pt.level);
+        // The lower half ends where addresses stop being canonical, its last page is kept out like the kernel one.
+        let end = (1u64 << (page_table.address_bits() - 1)) - arch::paging::get_page_frame_size() as u64;
+
+        page_table.share_top_level(kernel_pt, kernel_pt.kernel_half_index());
+        Ok(Self {
+            page_table,
+            regions: BTreeMap::new(),
+            start: USER_SPACE_START,
+            end,
+            user: true,
+        })
+    }
+
+    #[inline]
+    pub fn is_user(&self) -> bool {
+        self.user
+    }
+
+    // Load the page table on the current CPU.
+    #[inline]
+    pub fn activate(&self) {
+        self.page_table.load();
+    }
+
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        arch::paging::get_page_table_addr() == self.page_table.head
+    }
+
+    pub fn page_table(&mut self) -> &mut PageTable {
+        &mut self.page_table
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.regions.values()
+    }
+
+    // Region containing an address, if any.
+    pub fn find(&self, addr: VirtAddr) -> Option<&Region> {
+        self.regions
+            .range(..=Into::<u64>::into(addr))
+            .next_back()
+            .map(|(_, region)| region)
+            .filter(|region| region.contains(addr))
+    }
+
+    // Lowest free range of `length` bytes whose start is aligned on `alignment`.
+    pub fn find_free(&self, length: usize, alignment: usize) -> Option<VirtAddr> {
+        self.find_free_between(self.start, self.end, length, alignment)
+    }
+
+    // Same as find_free, restricted to the [start, end) window.
+    pub fn find_free_between(&self, start: u64, end: u64, length: usize, alignment: usize) -> Option<VirtAddr> {
+        let length = PageTable::align_up(length, arch::paging::get_page_frame_size()) as u64;
+        let alignment = alignment.max(arch::paging::get_page_frame_size()) as u64;
+        let end = end.min(self.end);
+        let mut cursor = start.max(self.start);
+
+        for region in self.regions.range(..end).map(|(_, region)| region) {
+            if region.end() <= cursor {
+                continue;
+            }
+            let candidate = PageTable::align_up(cursor, alignment);
+
+            if candidate.checked_add(length)? <= region.start.into() {
+                return VirtAddr::try_from(candidate).ok();
+            }
+            cursor = cursor.max(region.end());
+        }
+
+        let candidate = PageTable::align_up(cursor, alignment);
+
+        if candidate.checked_add(length)? <= end {
+            return VirtAddr::try_from(candidate).ok();
+        }
+        None
+    }
+
+    fn is_free(&self, start: u64, length: u64) -> bool {
+        let Some(end) = start.checked_add(length) else {
+            return false;
+        };
+
+        start >= self.start
+            && end <= self.end
+            && self
+                .regions
+                .range(..end)
+                .next_back()
+                .is_none_or(|(_, region)| region.end() <= start)
+    }
+
+    // Record a region that is already mapped in the page table (or must never be).
+    pub fn track(&mut self, region: Region) -> Result<(), VmError> {
+        let start: u64 = region.start.into();
+
+        if !region.start.is_aligned(arch::paging::get_page_frame_size()) || !self.is_free(start, region.length as u64) {
+            return Err(VmError::Overlap);
+        }
+        self.regions.insert(start, region);
+        Ok(())
+    }
+
+    // Record a region and map it in the page table.
+    pub fn map(&mut self, region: Region) -> Result<(), VmError> {
+        self.track(region)?;
+        if let Err(error) = self.populate(&region) {
+            self.regions.remove(&region.start.into());
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // Map `length` bytes anywhere in the address space.
+    pub fn allocate(
+        &mut self,
+        length: usize,
+        alignment: usize,
+        flags: PageEntryFlags,
+        backing: Backing,
+    ) -> Result<VirtAddr, VmError> {
+        let start = self.find_free(length, alignment).ok_or(VmError::NoFreeRange)?;
+
+        self.map(Region::new(start, length, flags, backing))?;
+        Ok(start)
+    }
+
+    // Remove the region starting at `start` and unmap it.
+    pub fn unmap(&mut self, start: VirtAddr) -> Result<Region, VmError> {
+        let region = self
+            .regions
+            .remove(&start.into())
+            .ok_or(VmError::NotFound)?;
+
+        self.depopulate(&region, region.length)?;
+        Ok(region)
+    }
+
+    // Resolve a fault on an address of this address space, returning an error
+    // if the access was illegal.
+    pub fn handle_fault(&mut self, fault: &PageFault) -> Result<(), VmError> {
+        let region = *self.find(fault.address).ok_or(VmError::NotFound)?;
+        let page = Page::containing(fault.address);
+
+        if let Backing::Guard(owner) = region.backing {
+            return Err(VmError::StackOverflow(owner));
+        }
+        if fault.present
+            && fault.write
+            && region.flags.contains(PageEntryFlags::ReadWrite)
+            && let Some((frame, flags)) = self.page_table.translate(page.start())
+            && flags.contains(PageEntryFlags::CopyOnWrite)
+        {
+            return self.copy_on_write(page, frame, region.flags);
+        }
+        if fault.present
+            || (fault.write && !region.flags.contains(PageEntryFlags::ReadWrite))
+            || (fault.user && !region.flags.contains(PageEntryFlags::User))
+            || (fault.execute && region.flags.contains(PageEntryFlags::ExecuteDisabled))
+        {
+            return Err(VmError::AccessViolation);
+        }
+        match region.backing {
+            Backing::Demand => {
+                let mut allocator = FRAME_ALLOCATOR.try_lock().ok_or(VmError::WouldDeadlock)?;
+
+                self.map_anonymous_page(&mut *allocator, page.start(), region.flags)
+            }
+            _ => Err(VmError::AccessViolation),
+        }
+    }
+
+    // Give the page a private copy of a shared frame, the last mapping of a frame
+    // takes it over without copying.
+    fn copy_on_write(&mut self, page: Page, frame: PhysAddr, flags: PageEntryFlags) -> Result<(), VmError> {
+        let pfsize = arch::paging::get_page_frame_size();
+        // Called from the page fault handler, which may have interrupted a frame allocation.
+        let mut allocator = FRAME_ALLOCATOR.try_lock().ok_or(VmError::WouldDeadlock)?;
+
+        if allocator.references(frame) == 1 {
+            self.page_table.protect(&mut *allocator, page.start(), pfsize, flags)?;
+            return Ok(());
+        }
+
+        let copy = allocator.allocate(false)?;
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                frame.as_hhdm().as_ptr::<u8>(),
+                copy.as_hhdm().as_mut_ptr::<u8>(),
+                pfsize,
+            );
+        }
+        if let Err(error) = self.page_table.map_page(&mut *allocator, copy, page.start(), flags) {
+            allocator.free(copy, 1);
+            return Err(error.into());
+        }
+        allocator.release(frame);
+        Ok(())
+    }
+
+    // Duplicate the address space into another page table. Anonymous memory is shared
+    // between both and only copied when one of them writes to it.
+    pub fn fork(&mut self, page_table: PageTable) -> Result<AddressSpace, VmError> {
+        let child = AddressSpace {
+            page_table,
+            regions: self.regions.clone(),
+            start: self.start,
+            end: self.end,
+            user: false,
+        };
+
+        self.fork_into(child)
+    }
+
+    // Same as fork, the child gets its own user page table and frees it when dropped.
+    pub fn fork_user(&mut self) -> Result<AddressSpace, VmError> {
+        let mut child = AddressSpace::new_user()?;
+
+        child.regions = self.regions.clone();
+        self.fork_into(child)
+    }
+
+    fn fork_into(&mut self, mut child: AddressSpace) -> Result<AddressSpace, VmError> {
+        let pfsize = arch::paging::get_page_frame_size();
+        // The region tree is on the heap, it must be copied before the allocator is held.
+        let mut allocator = FRAME_ALLOCATOR.lock();
+
+        for region in self.regions.values() {
+            match region.backing {
+                Backing::Reserved | Backing::Guard(_) => {}
+                Backing::Physical(phys_addr) => {
+                    child.page_table.map_page_range(
+                        &mut *allocator,
+                        phys_addr,
+                        region.start,
+                        region.flags,
+                        region.length,
+                    )?;
+                }
+                Backing::Anonymous | Backing::Demand => {
+                    for offset in (0..region.length).step_by(pfsize) {
+                        let Some((frame, flags)) = self.page_table.translate(region.start + offset) else {
+                            continue;
+                        };
+                        let shared = if region.flags.contains(PageEntryFlags::ReadWrite) {
+                            (flags - PageEntryFlags::ReadWrite) | PageEntryFlags::CopyOnWrite
+                        } else {
+                            flags
+                        };
+
+                        self.page_table.protect(&mut *allocator, region.start + offset, pfsize, shared)?;
+                        child.page_table.map_page(&mut *allocator, frame, region.start + offset, shared)?;
+                        allocator.reference(frame);
+                    }
+                }
+            }
+        }
+        Ok(child)
+    }
+
+    fn populate(&mut self, region: &Region) -> Result<(), VmError> {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+
+        match region.backing {
+            Backing::Reserved | Backing::Guard(_) | Backing::Demand => Ok(()),
+            Backing::Physical(phys_addr) => {
+                self.page_table
+                    .map_page_range(&mut *allocator, phys_addr, region.start, region.flags, region.length)?;
+                Ok(())
+            }
+            Backing::Anonymous => {
+                for offset in (0..region.length).step_by(arch::paging::get_page_frame_size()) {
+                    if let Err(error) = self.map_anonymous_page(&mut *allocator, region.start + offset, region.flags) {
+                        drop(allocator);
+                        // Give back what was mapped so far.
+                        self.depopulate(region, offset)?;
+                        return Err(error);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn map_anonymous_page(
+        &mut self,
+        allocator: &mut dyn PageFrameAllocator,
+        virt_addr: VirtAddr,
+        flags: PageEntryFlags,
+    ) -> Result<(), VmError> {
+        let frame = allocator.allocate(true)?;
+
+        if let Err(error) = self.page_table.map_page(allocator, frame, virt_addr, flags) {
+            allocator.free(frame, 1);
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    // Unmap the first `length` bytes of a region.
+    fn depopulate(&mut self, region: &Region, length: usize) -> Result<(), VmError> {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+
+        match region.backing {
+            Backing::Reserved | Backing::Guard(_) => {}
+            Backing::Physical(_) => {
+                self.page_table.unmap_page_range(&mut *allocator, region.start, length)?;
+            }
+            Backing::Anonymous | Backing::Demand => {
+                // Only the pages that were touched have a frame, and a child whose fork failed
+                // may be missing some of the others.
+                for offset in (0..length).step_by(arch::paging::get_page_frame_size()) {
+                    if self.page_table.translate(region.start + offset).is_none() {
+                        continue;
+                    }
+
+                    let frame = self.page_table.unmap_page(&mut *allocator, region.start + offset)?;
+
+                    allocator.release(frame);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    // User address spaces give back their frames and lower half tables, the kernel half is shared.
+    fn drop(&mut self) {
+        if !self.user {
+            return;
+        }
+        assert!(!self.is_active(), "Dropping the address space loaded on this CPU.");
+
+        let regions = core::mem::take(&mut self.regions);
+
+        for region in regions.values() {
+            // Physical memory isn't owned, its mappings go away with the tables.
+            if let Backing::Anonymous | Backing::Demand = region.backing
+                && let Err(error) = self.depopulate(region, region.length)
+            {
+                warning!("Failed to unmap region at 0x{:02x} of a dropped address space: {:?}", region.start, error);
+            }
+        }
+
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let kernel_half = self.page_table.kernel_half_index();
+
+        self.page_table.free_tables(&mut *allocator, 0..kernel_half);
+        allocator.free(
+            self.page_table.head,
+            arch::paging::get_page_level_size() / arch::paging::get_page_frame_size(),
+        );
+    }
+}
+
+// Switch to a user address space, or back to the kernel one. Called on context switch.
+pub fn switch_address_space(space: Option<Arc<Mutex<AddressSpace>>>) {
+    let previous = {
+        let mut current = CURRENT_USER_SPACE.lock();
+
+        match &space {
+            Some(space) => space.lock().activate(),
+            None => KERNEL_ADDRESS_SPACE
+                .lock()
+                .as_ref()
+                .expect("Address spaces can't be switched before memory::init.")
+                .activate(),
+        }
+        core::mem::replace(&mut *current, space)
+    };
+
+    // Dropping the last reference tears the address space down, which takes the frame allocator.
+    drop(previous);
+}
+
+// Entry point of the architecture page fault handlers.
+// Locks are never waited for, a fault while the address space or the frame allocator is busy cannot be resolved.
+pub fn handle_page_fault(fault: &PageFault) -> Result<(), VmError> {
+    if fault.address < arch::paging::get_kernel_space_start() {
+        let current = CURRENT_USER_SPACE.try_lock().ok_or(VmError::WouldDeadlock)?;
+        let mut space = current.as_ref().ok_or(VmError::NotFound)?.try_lock().ok_or(VmError::WouldDeadlock)?;
+
+        return space.handle_fault(fault);
+    }
+
+    let mut space = KERNEL_ADDRESS_SPACE.try_lock().ok_or(VmError::WouldDeadlock)?;
+
+    space.as_mut().ok_or(VmError::NotFound)?.handle_fault(fault)
+}
+
+#[cfg(test)]
+mod tests {
+    use limine::paging::Mode;
+
+    use crate::{KERNEL_CONTEXT, libs::{
+        arch::x86_64::memory::paging::PageEntryFlags,
+        generic::memory::{
+            address::{PhysAddr, VirtAddr},
+            paging::{PageTable, PaginationLevel},
+            vm::{AddressSpace, Backing, Region},
+        },
+    }};
+
+    #[test]
+    fn find_free_skips_regions() {
+        unsafe { KERNEL_CONTEXT.boot_info.paging_level = Some(Mode::FOUR_LEVEL) };
+        let mut space = AddressSpace::new(
+            PageTable::new(PhysAddr::from(0), PaginationLevel::Level4),
+            VirtAddr::try_from(0x10000).unwrap(),
+            VirtAddr::try_from(0x100000).unwrap(),
+        );
+
+        space
+            .track(Region::new(VirtAddr::try_from(0x10000).unwrap(), 0x3000, PageEntryFlags::empty(), Backing::Reserved))
+            .unwrap();
+        space
+            .track(Region::new(VirtAddr::try_from(0x14000).unwrap(), 0x1000, PageEntryFlags::empty(), Backing::Reserved))
+            .unwrap();
+        assert_eq!(Into::<u64>::into(space.find_free(0x1000, 0).unwrap()), 0x13000);
+        assert_eq!(Into::<u64>::into(space.find_free(0x2000, 0).unwrap()), 0x15000);
+        assert_eq!(Into::<u64>::into(space.find_free(0x1000, 0x10000).unwrap()), 0x20000);
+        assert!(space.find_free(0x100000, 0).is_none());
+        assert_eq!(Into::<u64>::into(space.find_free_between(0x11000, 0x20000, 0x1000, 0).unwrap()), 0x13000);
+        assert!(space.find_free_between(0x10000, 0x14800, 0x2000, 0).is_none());
+        assert!(space
+            .track(Region::new(VirtAddr::try_from(0x12000).unwrap(), 0x2000, PageEntryFlags::empty(), Backing::Reserved))
+            .is_err());
+        assert!(space.find(VirtAddr::try_from(0x14800).unwrap()).is_some());
+        assert!(space.find(VirtAddr::try_from(0x13000).unwrap()).is_none());
+    }
+}
@@ -1,3 +1,4 @@
+pub mod acpi;
 pub mod logging;
 pub mod memory;
 pub mod parsers;
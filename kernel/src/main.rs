@@ -1,10 +1,12 @@
 #![feature(cfg_select)]
+#![cfg_attr(not(test), feature(alloc_error_handler))]
+#![cfg_attr(feature = "heap-debug", feature(return_address))]
+#![cfg_attr(feature = "kasan", feature(sanitize))]
 #![cfg_attr(not(test), no_std)]
 #![cfg_attr(not(test), no_main)]
 #![allow(static_mut_refs)]
 #![allow(unused_unsafe)]
 #![allow(unconditional_panic)]
-#![feature(alloc_layout_extra)]
 
 pub mod context;
 pub mod libs;
@@ -20,6 +22,7 @@ use crate::context::{BootInfo, KernelContext};
 use crate::libs::arch::x86_64::serial;
 use crate::libs::generic::logging::logger::Logger;
 use crate::libs::generic::memory;
+use crate::libs::generic::memory::stack::{KernelStack, StackOwner};
 use crate::libs::{arch, drivers};
 use limine::BaseRevision;
 use limine::framebuffer::Framebuffer;
@@ -27,9 +30,12 @@ use limine::paging::Mode;
 use limine::request::{
     BootloaderInfoRequest, DateAtBootRequest, ExecutableAddressRequest, FramebufferRequest,
     HhdmRequest, MemoryMapRequest, MpRequest, PagingModeRequest, RequestsEndMarker,
-    RequestsStartMarker, StackSizeRequest,
+    RequestsStartMarker, RsdpRequest, StackSizeRequest,
 };
 
+// Stack the kernel keeps running on once it left the bootloader one.
+const BOOT_STACK_SIZE: usize = 1024 * 1024;
+
 #[used]
 #[unsafe(link_section = ".requests_start_marker")]
 static _START_MARKER: RequestsStartMarker = RequestsStartMarker::new();
@@ -74,6 +80,10 @@ static HHDM_REQUEST: HhdmRequest = HhdmRequest::new();
 #[unsafe(link_section = ".requests")]
 static PLEVEL_REQUEST: PagingModeRequest = PagingModeRequest::new().with_mode(Mode::FIVE_LEVEL);
 
+#[used]
+#[unsafe(link_section = ".requests")]
+static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
+
 #[used]
 #[unsafe(link_section = ".requests_end_marker")]
 static _END_MARKER: RequestsEndMarker = RequestsEndMarker::new();
@@ -90,6 +100,7 @@ static mut KERNEL_CONTEXT: KernelContext<'static> = KernelContext {
         rtc_boot: None,
         paging_level: None,
         memory_map: None,
+        rsdp: None,
     },
 };
 
@@ -105,6 +116,17 @@ fn rust_panic(_info: &core::panic::PanicInfo) -> ! {
     hcf();
 }
 
+#[cfg(not(test))]
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    memory::log_usage();
+    panic!(
+        "Out of memory allocating {} bytes (alignment {})",
+        layout.size(),
+        layout.align()
+    );
+}
+
 fn hcf() -> ! {
     loop {
         unsafe {
@@ -135,6 +157,7 @@ fn populate_boot_info(boot_info: &mut BootInfo) {
             .unwrap_or(Mode::FOUR_LEVEL),
     );
     boot_info.memory_map = KMMAP_REQUEST.get_response();
+    boot_info.rsdp = RSDP_REQUEST.get_response().map(|r| r.address() as u64);
 }
 
 fn print_boot_info(boot_info: &BootInfo) {
@@ -206,7 +229,31 @@ unsafe extern "C" fn kmain() -> ! {
 
     info!("Kernel started successully !");
     arch::init();
-    memory::init(KMMAP_REQUEST.get_response());
+    memory::init(
+        KMMAP_REQUEST.get_response(),
+        unsafe { KERNEL_CONTEXT.boot_info.rsdp.map(memory::address::PhysAddr::from) },
+    );
+    arch::late_init();
+
+    // The bootloader stack is in reclaimable memory, leave it before giving it away.
+    let stack = KernelStack::allocate(BOOT_STACK_SIZE, StackOwner::Thread(0))
+        .expect("Failed to allocate the boot stack.");
+
+    unsafe { arch::switch_stack(stack.leak(), kmain_late) }
+}
+
+extern "C" fn kmain_late() -> ! {
+    // Everything we need from the bootloader responses has been copied by now,
+    // and the ACPI tables (SRAT and SLIT) were parsed by memory::init.
+    unsafe {
+        KERNEL_CONTEXT.framebuffer = None;
+        KERNEL_CONTEXT.boot_info.memory_map = None;
+        KERNEL_CONTEXT.boot_info.rsdp = None;
+
+        let reclaimed = memory::reclaim_bootloader_memory() + memory::reclaim_acpi_memory();
+
+        debug!("Reclaimed {}KiB of bootloader and ACPI memory", reclaimed / 1024);
+    }
 
     // We can now allocate memory.
     unsafe {